@@ -0,0 +1,108 @@
+// Compare the pixel maps against the original implementation, which sorted
+// one line after another with the sort function called in the comparator.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use pixel_unsort::art::*;
+use pixel_unsort::core::*;
+use pixel_unsort::matrix::Matrix;
+use pixel_unsort::sortfns::*;
+use pixel_unsort::spans::*;
+use rayon::prelude::*;
+
+const SIZES: [(&str, u32, u32); 2] = [("4K", 3840, 2160), ("8K", 7680, 4320)];
+
+fn test_image(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        let v = ((x * 37 + y * 101) % 251) as u8;
+        Rgba([v, v.wrapping_mul(3), 255 - v, 255])
+    }))
+}
+
+// The original sort functions and pixel maps, as they were before any of the
+// changes measured here.
+
+type SortFn = fn(Rgba<u8>) -> i16;
+
+type OldGrid = Matrix<(usize, usize)>;
+
+fn old_hue(c: Rgba<u8>) -> i16 {
+    hue(c).0 as i16
+}
+
+fn old_pixel_map_row(
+    img: &DynamicImage,
+    f: SortFn,
+    order: SortOrder,
+    grid: Option<OldGrid>,
+) -> OldGrid {
+    let mut px_map = match grid {
+        Some(g) => g,
+        None => Matrix::generate(img.width() as usize, img.height() as usize, |x, y| (x, y)),
+    };
+    for y in 0..px_map.height {
+        let mut row = px_map[y].to_vec();
+        row.par_sort_by_key(|x| order.dir() * f(img.get_pixel(x.0 as u32, x.1 as u32)));
+        let mut indices = (0..row.len()).collect::<Vec<_>>();
+        indices.par_sort_by_key(|i| row[*i].0);
+        let row1 = indices.par_iter().map(|i| (*i, y)).collect::<Vec<_>>();
+        px_map[y].par_iter_mut().enumerate().for_each(|(i, e)| {
+            *e = row1[i];
+        });
+    }
+    px_map
+}
+
+fn old_pixel_map_column(
+    img: &DynamicImage,
+    f: SortFn,
+    order: SortOrder,
+    grid: Option<OldGrid>,
+) -> OldGrid {
+    let mut px_map = match grid {
+        Some(g) => g,
+        None => Matrix::generate(img.width() as usize, img.height() as usize, |x, y| (x, y)),
+    };
+    for x in 0..px_map.width {
+        let mut column = px_map.get_column(x);
+        column.par_sort_by_key(|y| order.dir() * f(img.get_pixel(y.0 as u32, y.1 as u32)));
+        let mut indices = (0..column.len()).collect::<Vec<_>>();
+        indices.par_sort_by_key(|i| column[*i].1);
+        let column1 = indices.par_iter().map(|i| (x, *i)).collect::<Vec<_>>();
+        for y in 0..px_map.height {
+            px_map[y][x] = column1[y]
+        }
+    }
+    px_map
+}
+
+fn pixel_map_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pixel_map");
+    group.sample_size(10);
+    for (name, width, height) in SIZES {
+        let img = test_image(width, height);
+        let seg = Segmenter::new(&img, SpanMode::Full, None);
+        group.bench_function(BenchmarkId::new("row/original", name), |b| {
+            b.iter(|| old_pixel_map_row(&img, old_hue, SortOrder::Ascending, None))
+        });
+        group.bench_function(BenchmarkId::new("row/current", name), |b| {
+            b.iter(|| {
+                let keys = KeyBuffer::new(&img, &hue);
+                pixel_map_row(&keys, SortOrder::Ascending, &seg, None)
+            })
+        });
+        group.bench_function(BenchmarkId::new("column/original", name), |b| {
+            b.iter(|| old_pixel_map_column(&img, old_hue, SortOrder::Ascending, None))
+        });
+        group.bench_function(BenchmarkId::new("column/current", name), |b| {
+            b.iter(|| {
+                let keys = KeyBuffer::new(&img, &hue);
+                pixel_map_column(&keys, SortOrder::Ascending, &seg, None)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pixel_map_bench);
+criterion_main!(benches);
@@ -1,5 +1,6 @@
 use crate::art::*;
 use crate::core::*;
+use crate::sortfns::{composite_fn, key_fn, SortKeyFn};
 use directories::UserDirs;
 use egui::ComboBox;
 use egui::{Button, ColorImage, Frame, TextureHandle, Vec2};
@@ -30,6 +31,112 @@ fn to_color_image(img: &RgbaImage, width: u32, height: u32) -> ColorImage {
         &img.into_vec(),
     )
 }
+// A radio button for an enum variant that holds data, choosing it when it is
+// not already selected resets the value to the variant's default.
+fn variant_radio<T>(ui: &mut egui::Ui, value: &mut T, selected: bool, default: T, text: &str) {
+    if ui.radio(selected, text).clicked() && !selected {
+        *value = default;
+    }
+}
+
+fn key_name(sort_key: &SortKey) -> String {
+    match sort_key {
+        SortKey::Distance { .. } => "Distance".to_string(),
+        SortKey::HueFrom(_) => "Hue From".to_string(),
+        key => format!("{key:?}"),
+    }
+}
+
+// A combo box to choose a sort key, with the reference colour for distances.
+fn key_combo(ui: &mut egui::Ui, label: &str, key: &mut SortKey) {
+    ComboBox::from_label(label)
+        .width(125.0)
+        .selected_text(key_name(key))
+        .show_ui(ui, |ui| {
+            ui.style_mut().wrap = Some(false);
+            ui.set_min_width(60.0);
+            ui.selectable_value(key, SortKey::Lightness, "Lightness");
+            ui.selectable_value(key, SortKey::Hue, "Hue");
+            let hue_from = matches!(key, SortKey::HueFrom(_));
+            if ui.selectable_label(hue_from, "Hue From").clicked() && !hue_from {
+                *key = SortKey::HueFrom(HueOrigin::Degrees(0.0));
+            }
+            ui.selectable_value(key, SortKey::Saturation, "Saturation");
+            ui.selectable_value(key, SortKey::Red, "Red");
+            ui.selectable_value(key, SortKey::Green, "Green");
+            ui.selectable_value(key, SortKey::Blue, "Blue");
+            ui.selectable_value(key, SortKey::Alpha, "Alpha");
+            ui.selectable_value(key, SortKey::MaxChannel, "Max Channel");
+            ui.selectable_value(key, SortKey::MinChannel, "Min Channel");
+            ui.selectable_value(key, SortKey::OkLightness, "OkLightness");
+            ui.selectable_value(key, SortKey::OkChroma, "OkChroma");
+            ui.selectable_value(key, SortKey::OkHue, "OkHue");
+            ui.selectable_value(key, SortKey::LabL, "Lab L*");
+            ui.selectable_value(key, SortKey::LabA, "Lab a*");
+            ui.selectable_value(key, SortKey::LabB, "Lab b*");
+            let distance = matches!(key, SortKey::Distance { .. });
+            if ui.selectable_label(distance, "Distance").clicked() && !distance {
+                *key = SortKey::Distance {
+                    color: [255, 0, 0],
+                    space: ColorSpace::OkLab,
+                };
+            }
+        });
+    match key {
+        SortKey::Distance { color, space } => {
+            ui.horizontal(|ui| {
+                egui::color_picker::color_edit_button_srgb(ui, color);
+                ui.label("Reference");
+                ui.radio_value(space, ColorSpace::OkLab, "OKLab");
+                ui.radio_value(space, ColorSpace::Lab, "CIELAB");
+            });
+        }
+        SortKey::HueFrom(origin) => {
+            let mut auto = *origin == HueOrigin::Auto;
+            if ui.checkbox(&mut auto, "Largest Gap").changed() {
+                *origin = if auto {
+                    HueOrigin::Auto
+                } else {
+                    HueOrigin::Degrees(0.0)
+                };
+            }
+            if let HueOrigin::Degrees(degrees) = origin {
+                ui.add(
+                    egui::Slider::new(degrees, 0.0..=360.0)
+                        .text("Origin")
+                        .suffix("°"),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+// The sort function for the chosen key or composite of keys.
+fn sort_fn(sort_key: SortKey, composite: &Option<Composite>) -> Box<dyn SortKeyFn> {
+    match composite {
+        Some(composite) => composite_fn(composite),
+        None => key_fn(sort_key),
+    }
+}
+
+fn composite_name(composite: &Option<Composite>) -> &'static str {
+    match composite {
+        None => "Single Key",
+        Some(Composite::TieBreak(_)) => "Tie-Break",
+        Some(Composite::Blend(_)) => "Blend",
+    }
+}
+
+fn span_name(span_mode: &SpanMode) -> &'static str {
+    match span_mode {
+        SpanMode::Full => "Full",
+        SpanMode::Threshold { .. } => "Threshold",
+        SpanMode::Edge { .. } => "Edge",
+        SpanMode::Random { .. } => "Random",
+    }
+}
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
@@ -41,10 +148,13 @@ pub struct PixelUnsortApp {
 
     sort_img_path: Option<String>,
     unsort_img_path: Option<String>,
+    mask_img_path: Option<String>,
     sort_by: SortBy,
     sort_key: SortKey,
+    composite: Option<Composite>,
     row_sort_order: SortOrder,
     col_sort_order: SortOrder,
+    span_mode: SpanMode,
     pre_sort: bool,
 }
 
@@ -53,12 +163,15 @@ impl Default for PixelUnsortApp {
         Self {
             sort_img_path: None,
             unsort_img_path: None,
+            mask_img_path: None,
             img: RgbaImage::new(1, 1),
             texture: None,
             sort_by: SortBy::Row,
             sort_key: SortKey::Lightness,
+            composite: None,
             row_sort_order: SortOrder::Ascending,
             col_sort_order: SortOrder::Ascending,
+            span_mode: SpanMode::Full,
             pre_sort: false,
         }
     }
@@ -142,6 +255,29 @@ impl eframe::App for PixelUnsortApp {
                 ui.add_space(SPACE);
                 ui.separator();
                 ui.add_space(SPACE);
+                ui.horizontal(|ui| {
+                    if ui
+                        .add(Button::new("Mask Image Path").min_size(Vec2::new(125.0, 25.0)))
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("image", &["png", "jpg", "jpeg"])
+                            .pick_file()
+                        {
+                            self.mask_img_path = Some(path.display().to_string());
+                        }
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.mask_img_path = None;
+                    }
+                });
+                ui.add_space(SPACE);
+                if let Some(picked_path) = &self.mask_img_path {
+                    ui.label(picked_path);
+                }
+                ui.add_space(SPACE);
+                ui.separator();
+                ui.add_space(SPACE);
                 ui.checkbox(&mut self.pre_sort, "Pre-Sort");
                 ui.add_space(SPACE);
                 ui.horizontal(|ui| {
@@ -151,17 +287,122 @@ impl eframe::App for PixelUnsortApp {
                     ui.radio_value(&mut self.sort_by, SortBy::RowCol, "RowCol");
                     ui.radio_value(&mut self.sort_by, SortBy::Nothing, "Nothing");
                 });
+                ui.horizontal(|ui| {
+                    let sort_by = self.sort_by;
+                    let angle = matches!(sort_by, SortBy::Angle(_));
+                    variant_radio(ui, &mut self.sort_by, angle, SortBy::Angle(45.0), "Angle");
+                    let radial = matches!(sort_by, SortBy::Radial { .. });
+                    let centre = SortBy::Radial { cx: 0.5, cy: 0.5 };
+                    variant_radio(ui, &mut self.sort_by, radial, centre, "Radial");
+                    let rings = matches!(sort_by, SortBy::Rings { .. });
+                    let centre = SortBy::Rings { cx: 0.5, cy: 0.5 };
+                    variant_radio(ui, &mut self.sort_by, rings, centre, "Rings");
+                    ui.radio_value(&mut self.sort_by, SortBy::Hilbert, "Hilbert");
+                    ui.radio_value(&mut self.sort_by, SortBy::ZOrder, "Z-Order");
+                    ui.radio_value(&mut self.sort_by, SortBy::Global, "Global");
+                });
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut self.sort_by,
+                        SortBy::Spiral { inward: true },
+                        "Spiral In",
+                    );
+                    ui.radio_value(
+                        &mut self.sort_by,
+                        SortBy::Spiral { inward: false },
+                        "Spiral Out",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    let sort_by = self.sort_by;
+                    let noise = matches!(sort_by, SortBy::Flow(FlowField::Noise { .. }));
+                    let field = SortBy::Flow(FlowField::Noise {
+                        seed: 0,
+                        scale: 0.01,
+                    });
+                    variant_radio(ui, &mut self.sort_by, noise, field, "Noise Flow");
+                    ui.radio_value(
+                        &mut self.sort_by,
+                        SortBy::Flow(FlowField::Gradient),
+                        "Gradient Flow",
+                    );
+                });
+                match &mut self.sort_by {
+                    SortBy::Angle(degrees) => {
+                        ui.add(
+                            egui::Slider::new(degrees, 0.0..=360.0)
+                                .text("Angle")
+                                .suffix("°"),
+                        );
+                    }
+                    SortBy::Radial { cx, cy } | SortBy::Rings { cx, cy } => {
+                        ui.add(egui::Slider::new(cx, 0.0..=1.0).text("Centre X"));
+                        ui.add(egui::Slider::new(cy, 0.0..=1.0).text("Centre Y"));
+                    }
+                    SortBy::Flow(FlowField::Noise { seed, scale }) => {
+                        ui.add(
+                            egui::Slider::new(scale, 0.001..=0.1)
+                                .logarithmic(true)
+                                .text("Scale"),
+                        );
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(seed));
+                            ui.label("Seed");
+                        });
+                    }
+                    _ => {}
+                }
                 ui.add_space(SPACE);
-                ComboBox::from_label("Sort Key")
+                ComboBox::from_label("Combine")
                     .width(125.0)
-                    .selected_text(format!("{:?}", self.sort_key))
+                    .selected_text(composite_name(&self.composite))
                     .show_ui(ui, |ui| {
                         ui.style_mut().wrap = Some(false);
                         ui.set_min_width(60.0);
-                        ui.selectable_value(&mut self.sort_key, SortKey::Lightness, "Lightness");
-                        ui.selectable_value(&mut self.sort_key, SortKey::Hue, "Hue");
-                        ui.selectable_value(&mut self.sort_key, SortKey::Saturation, "Saturation");
+                        ui.selectable_value(&mut self.composite, None, "Single Key");
+                        let tie_break = matches!(self.composite, Some(Composite::TieBreak(_)));
+                        if ui.selectable_label(tie_break, "Tie-Break").clicked() && !tie_break {
+                            let keys = vec![self.sort_key, SortKey::Hue];
+                            self.composite = Some(Composite::TieBreak(keys));
+                        }
+                        let blend = matches!(self.composite, Some(Composite::Blend(_)));
+                        if ui.selectable_label(blend, "Blend").clicked() && !blend {
+                            let keys = vec![(self.sort_key, 0.7), (SortKey::Hue, 0.3)];
+                            self.composite = Some(Composite::Blend(keys));
+                        }
                     });
+                match &mut self.composite {
+                    None => key_combo(ui, "Sort Key", &mut self.sort_key),
+                    Some(Composite::TieBreak(keys)) => {
+                        for (key, label) in
+                            keys.iter_mut().zip(["Primary", "Secondary", "Tertiary"])
+                        {
+                            key_combo(ui, label, key);
+                        }
+                        ui.horizontal(|ui| {
+                            if keys.len() < 3 && ui.button("Add Key").clicked() {
+                                keys.push(SortKey::Lightness);
+                            }
+                            if keys.len() > 1 && ui.button("Remove Key").clicked() {
+                                keys.pop();
+                            }
+                        });
+                    }
+                    Some(Composite::Blend(keys)) => {
+                        for (i, (key, weight)) in keys.iter_mut().enumerate() {
+                            key_combo(ui, &format!("Key {}", i + 1), key);
+                            ui.add(egui::Slider::new(weight, -1.0..=1.0).text("Weight"));
+                        }
+                        ui.horizontal(|ui| {
+                            if ui.button("Add Key").clicked() {
+                                keys.push((SortKey::Lightness, 0.0));
+                            }
+                            if keys.len() > 1 && ui.button("Remove Key").clicked() {
+                                keys.pop();
+                            }
+                        });
+                    }
+                }
                 ui.add_space(SPACE);
                 ComboBox::from_label("Row Order")
                     .width(125.0)
@@ -199,6 +440,62 @@ impl eframe::App for PixelUnsortApp {
                         );
                     });
                 ui.add_space(SPACE);
+                ComboBox::from_label("Spans")
+                    .width(125.0)
+                    .selected_text(span_name(&self.span_mode))
+                    .show_ui(ui, |ui| {
+                        ui.style_mut().wrap = Some(false);
+                        ui.set_min_width(60.0);
+                        ui.selectable_value(&mut self.span_mode, SpanMode::Full, "Full");
+                        let threshold = matches!(self.span_mode, SpanMode::Threshold { .. });
+                        if ui.selectable_label(threshold, "Threshold").clicked() && !threshold {
+                            self.span_mode = SpanMode::Threshold {
+                                lower: 64,
+                                upper: 192,
+                            };
+                        }
+                        let edge = matches!(self.span_mode, SpanMode::Edge { .. });
+                        if ui.selectable_label(edge, "Edge").clicked() && !edge {
+                            self.span_mode = SpanMode::Edge { threshold: 200 };
+                        }
+                        let random = matches!(self.span_mode, SpanMode::Random { .. });
+                        if ui.selectable_label(random, "Random").clicked() && !random {
+                            self.span_mode = SpanMode::Random {
+                                min: 10,
+                                max: 100,
+                                skip: 0.0,
+                                seed: 0,
+                            };
+                        }
+                    });
+                match &mut self.span_mode {
+                    SpanMode::Full => {}
+                    SpanMode::Threshold { lower, upper } => {
+                        ui.add_space(SPACE);
+                        ui.add(egui::Slider::new(lower, 0..=255).text("Lower"));
+                        ui.add(egui::Slider::new(upper, 0..=255).text("Upper"));
+                    }
+                    SpanMode::Edge { threshold } => {
+                        ui.add_space(SPACE);
+                        ui.add(egui::Slider::new(threshold, 0..=1000).text("Edge Threshold"));
+                    }
+                    SpanMode::Random {
+                        min,
+                        max,
+                        skip,
+                        seed,
+                    } => {
+                        ui.add_space(SPACE);
+                        ui.add(egui::Slider::new(min, 1..=1000).text("Min Length"));
+                        ui.add(egui::Slider::new(max, 1..=1000).text("Max Length"));
+                        ui.add(egui::Slider::new(skip, 0.0..=1.0).text("Skip"));
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(seed));
+                            ui.label("Seed");
+                        });
+                    }
+                }
+                ui.add_space(SPACE);
                 if ui
                     .add(Button::new("Swap Images").min_size(Vec2::new(125.0, 25.0)))
                     .clicked()
@@ -216,6 +513,10 @@ impl eframe::App for PixelUnsortApp {
                                 .add(Button::new("Unsort Image").min_size(Vec2::new(125.0, 25.0)))
                                 .clicked()
                             {
+                                let mask = self
+                                    .mask_img_path
+                                    .as_ref()
+                                    .and_then(|p| image::open(p).ok());
                                 if let Ok(img1) = image::open(sort_path) {
                                     if let Ok(img2) = image::open(unsort_path) {
                                         let size = dims(img1.width() as f32, img1.height() as f32);
@@ -223,10 +524,12 @@ impl eframe::App for PixelUnsortApp {
                                             &img1,
                                             &img2,
                                             self.sort_by,
-                                            self.sort_key,
+                                            sort_fn(self.sort_key, &self.composite).as_ref(),
                                             DrawType::Unsort,
                                             self.row_sort_order,
                                             self.col_sort_order,
+                                            self.span_mode,
+                                            mask.as_ref(),
                                             self.pre_sort,
                                         );
                                         self.texture = Some(ui.ctx().load_texture(
@@ -242,16 +545,22 @@ impl eframe::App for PixelUnsortApp {
                                 .add(Button::new("Sort Image").min_size(Vec2::new(125.0, 25.0)))
                                 .clicked()
                             {
+                                let mask = self
+                                    .mask_img_path
+                                    .as_ref()
+                                    .and_then(|p| image::open(p).ok());
                                 if let Ok(img1) = image::open(sort_path) {
                                     let size = dims(img1.width() as f32, img1.height() as f32);
                                     self.img = draw(
                                         &img1,
                                         &img1,
                                         self.sort_by,
-                                        self.sort_key,
+                                        sort_fn(self.sort_key, &self.composite).as_ref(),
                                         DrawType::Sort,
                                         self.row_sort_order,
                                         self.col_sort_order,
+                                        self.span_mode,
+                                        mask.as_ref(),
                                         self.pre_sort,
                                     );
                                     self.texture = Some(ui.ctx().load_texture(
@@ -1,187 +1,1005 @@
 use crate::core::*;
-use crate::matrix::*;
+use crate::paths::*;
 use crate::sortfns::*;
+use crate::spans::*;
 use image::imageops::FilterType;
 use image::*;
 use rayon::prelude::*;
+use std::borrow::Cow;
+use std::ops::Range;
 
-// Generate an image grid with the location of each pixel in the image.
-// Sort the pixels in each row by the sort function.
-pub fn pixel_map_row(
-    img: &DynamicImage,
-    f: SortFn,
+// Generate a pixel map with the location of each pixel in the image. Sort
+// the pixels in each span of each line by their keys. The lines must be
+// disjoint and inside the image, any pixels not on a line stay in place. If a
+// map is given its pixels are sorted again from where it moved them, the keys
+// and spans are those of the pixels at their source. It must be the size of
+// the image and send no two pixels to the same place. Anything else is an
+// error.
+pub fn pixel_map_lines<F: SortKeyFn + ?Sized>(
+    keys: &KeyBuffer<'_, F>,
     order: SortOrder,
-    grid: Option<ImgGrid>,
-) -> ImgGrid {
-    let mut px_map = match grid {
-        Some(g) => g,
-        None => Matrix::generate(img.width() as usize, img.height() as usize, |x, y| (x, y)),
-    };
-    for y in 0..px_map.height {
-        let mut row = px_map[y].to_vec();
-        row.par_sort_by_key(|x| order.dir() * f(img.get_pixel(x.0 as u32, x.1 as u32)));
-        let mut indices = (0..row.len()).collect::<Vec<_>>();
-        indices.par_sort_by_key(|i| row[*i].0);
-        let row1 = indices.par_iter().map(|i| (*i, y)).collect::<Vec<_>>();
-        px_map[y].par_iter_mut().enumerate().for_each(|(i, e)| {
-            *e = row1[i];
-        });
+    seg: &Segmenter,
+    lines: &[Line],
+    grid: Option<PixelMap>,
+) -> Result<PixelMap, String> {
+    check_lines(keys.width(), keys.height(), lines)?;
+    if let Some(g) = &grid {
+        if (g.width, g.height) != (keys.width(), keys.height()) {
+            return Err(format!(
+                "a {}x{} pixel map does not fit a {}x{} image",
+                g.width,
+                g.height,
+                keys.width(),
+                keys.height()
+            ));
+        }
+        g.check()?;
     }
-    px_map
+    Ok(map_along(keys, order, seg, Lines::Given(lines), grid))
 }
 
-// Generate an image grid with the location of each pixel in the image.
-// Sort the pixels in each column by the sort function.
-pub fn pixel_map_column(
-    img: &DynamicImage,
-    f: SortFn,
+// The lines to sort, either given or the rows or columns of an image made one
+// at a time, so that the lines of a large image are never all held at once.
+#[derive(Clone, Copy)]
+enum Lines<'a> {
+    Given(&'a [Line]),
+    Rows(usize, usize),
+    Columns(usize, usize),
+}
+
+impl Lines<'_> {
+    fn count(&self) -> usize {
+        match self {
+            Lines::Given(lines) => lines.len(),
+            Lines::Rows(_, height) => *height,
+            Lines::Columns(width, _) => *width,
+        }
+    }
+
+    fn len(&self, n: usize) -> usize {
+        match self {
+            Lines::Given(lines) => lines[n].len(),
+            Lines::Rows(width, _) => *width,
+            Lines::Columns(_, height) => *height,
+        }
+    }
+
+    fn get(&self, n: usize) -> Cow<'_, [(usize, usize)]> {
+        match self {
+            Lines::Given(lines) => Cow::Borrowed(&lines[n]),
+            Lines::Rows(width, _) => (0..*width).map(|x| (x, n)).collect(),
+            Lines::Columns(_, height) => (0..*height).map(|y| (n, y)).collect(),
+        }
+    }
+
+    // Consecutive runs of lines with about BATCH pixels between them, sorted
+    // together in parallel before their pixels are moved.
+    fn batches(&self) -> Vec<Range<usize>> {
+        const BATCH: usize = 1 << 20;
+        let mut batches = Vec::new();
+        let (mut start, mut pixels) = (0, 0);
+        for n in 0..self.count() {
+            pixels += self.len(n);
+            if pixels >= BATCH {
+                batches.push(start..n + 1);
+                (start, pixels) = (n + 1, 0);
+            }
+        }
+        if start < self.count() {
+            batches.push(start..self.count());
+        }
+        batches
+    }
+}
+
+// pixel_map_lines for lines that are known to be valid.
+fn map_along<F: SortKeyFn + ?Sized>(
+    keys: &KeyBuffer<'_, F>,
     order: SortOrder,
-    grid: Option<ImgGrid>,
-) -> ImgGrid {
+    seg: &Segmenter,
+    lines: Lines<'_>,
+    grid: Option<PixelMap>,
+) -> PixelMap {
+    let sources = grid.as_ref().map(PixelMap::inverse);
     let mut px_map = match grid {
         Some(g) => g,
-        None => Matrix::generate(img.width() as usize, img.height() as usize, |x, y| (x, y)),
+        None => PixelMap::identity(keys.width(), keys.height()),
     };
-    for x in 0..px_map.width {
-        let mut column = px_map.get_column(x);
-        column.par_sort_by_key(|y| order.dir() * f(img.get_pixel(y.0 as u32, y.1 as u32)));
-        let mut indices = (0..column.len()).collect::<Vec<_>>();
-        indices.par_sort_by_key(|i| column[*i].1);
-        let column1 = indices.par_iter().map(|i| (x, *i)).collect::<Vec<_>>();
-        for y in 0..px_map.height {
-            px_map[y][x] = column1[y]
+    // Sort a batch of lines in parallel, then move their pixels.
+    for batch in lines.batches() {
+        let orders = batch
+            .into_par_iter()
+            .map(|n| {
+                let line = lines.get(n);
+                let sourced = sources
+                    .as_ref()
+                    .map(|s| line.iter().map(|(x, y)| s.get(*x, *y)).collect::<Vec<_>>());
+                let coords = sourced.as_deref().unwrap_or(&line);
+                let line_keys = keys.line(coords);
+                let sorted = sort_order(&line_keys, &seg.spans(&line_keys, coords, n), order);
+                (line, sourced, sorted)
+            })
+            .collect::<Vec<_>>();
+        for (line, sourced, sorted) in orders {
+            let coords = sourced.as_deref().unwrap_or(&line);
+            for (i, j) in sorted.into_iter().enumerate() {
+                let (x, y) = coords[j];
+                px_map.set(x, y, line[i]);
+            }
         }
     }
     px_map
 }
 
-// Pixel sort a DynamicImage by rows.
-pub fn pixel_sort_row(img: &DynamicImage, f: SortFn, order: SortOrder) -> RgbaImage {
-    let mut data: Vec<u8> = Vec::with_capacity(16 * img.width() as usize * img.height() as usize);
+// Generate a pixel map with the location of each pixel in the image. Sort
+// the pixels in each span of each row by their keys.
+pub fn pixel_map_row<F: SortKeyFn + ?Sized>(
+    keys: &KeyBuffer<'_, F>,
+    order: SortOrder,
+    seg: &Segmenter,
+    grid: Option<PixelMap>,
+) -> PixelMap {
+    map_along(
+        keys,
+        order,
+        seg,
+        Lines::Rows(keys.width(), keys.height()),
+        grid,
+    )
+}
+
+// Generate a pixel map with the location of each pixel in the image. Sort
+// the pixels in each span of each column by their keys.
+pub fn pixel_map_column<F: SortKeyFn + ?Sized>(
+    keys: &KeyBuffer<'_, F>,
+    order: SortOrder,
+    seg: &Segmenter,
+    grid: Option<PixelMap>,
+) -> PixelMap {
+    map_along(
+        keys,
+        order,
+        seg,
+        Lines::Columns(keys.width(), keys.height()),
+        grid,
+    )
+}
+
+// Pixel sort a DynamicImage along disjoint lines by the keys of its pixels,
+// pixels that are not on any line stay in place. Lines that overlap or leave
+// the image are an error.
+pub fn pixel_sort_lines<F: SortKeyFn + ?Sized>(
+    img: &DynamicImage,
+    keys: &KeyBuffer<'_, F>,
+    order: SortOrder,
+    seg: &Segmenter,
+    lines: &[Line],
+) -> Result<RgbaImage, String> {
+    check_lines(keys.width(), keys.height(), lines)?;
+    Ok(sort_along(img, keys, order, seg, Lines::Given(lines)))
+}
+
+// pixel_sort_lines for lines that are known to be valid.
+fn sort_along<F: SortKeyFn + ?Sized>(
+    img: &DynamicImage,
+    keys: &KeyBuffer<'_, F>,
+    order: SortOrder,
+    seg: &Segmenter,
+    lines: Lines<'_>,
+) -> RgbaImage {
     let buffer = img.to_rgba8();
-    for buf_row in buffer.rows() {
-        let mut row = Vec::with_capacity(buf_row.len());
-        for p in buf_row {
-            row.push(*p);
-        }
-        row.par_sort_by_key(|p| order.dir() * f(*p));
-        for p in row {
-            for c in p.channels() {
-                data.push(*c);
+    let mut out_image = buffer.clone();
+    for batch in lines.batches() {
+        let orders = batch
+            .into_par_iter()
+            .map(|n| {
+                let line = lines.get(n);
+                let line_keys = keys.line(&line);
+                let sorted = sort_order(&line_keys, &seg.spans(&line_keys, &line, n), order);
+                (line, sorted)
+            })
+            .collect::<Vec<_>>();
+        for (line, sorted) in orders {
+            for (i, j) in sorted.into_iter().enumerate() {
+                let ((x, y), (x1, y1)) = (line[i], line[j]);
+                out_image.put_pixel(x as u32, y as u32, *buffer.get_pixel(x1 as u32, y1 as u32));
             }
         }
     }
-    ImageBuffer::from_vec(img.width(), img.height(), data).unwrap()
+    out_image
+}
+
+// Pixel sort a DynamicImage by rows.
+pub fn pixel_sort_row<F: SortKeyFn + ?Sized>(
+    img: &DynamicImage,
+    keys: &KeyBuffer<'_, F>,
+    order: SortOrder,
+    seg: &Segmenter,
+) -> RgbaImage {
+    sort_along(
+        img,
+        keys,
+        order,
+        seg,
+        Lines::Rows(keys.width(), keys.height()),
+    )
 }
 
 // Pixel sort a DynamicImage by columns.
-pub fn pixel_sort_column(img: &DynamicImage, f: SortFn, order: SortOrder) -> RgbaImage {
-    let rotate_img = img.rotate90();
-    let sorted_img = pixel_sort_row(&rotate_img, f, -order);
-    let dyn_img = DynamicImage::ImageRgba8(sorted_img);
-    dyn_img.rotate270().into_rgba8()
+pub fn pixel_sort_column<F: SortKeyFn + ?Sized>(
+    img: &DynamicImage,
+    keys: &KeyBuffer<'_, F>,
+    order: SortOrder,
+    seg: &Segmenter,
+) -> RgbaImage {
+    sort_along(
+        img,
+        keys,
+        order,
+        seg,
+        Lines::Columns(keys.width(), keys.height()),
+    )
 }
 
-// Unsort the image using the pixel map.
-pub fn pixel_unsort(img: &DynamicImage, px_map: &ImgGrid) -> RgbaImage {
-    let mut out_image = RgbaImage::new(img.width(), img.height());
-    for y in 0..px_map.height {
-        for x in 0..px_map.width {
-            let (x1, y1) = px_map[y][x];
-            let p = img.get_pixel(x1 as u32, y1 as u32);
-            out_image.put_pixel(x as u32, y as u32, p)
+// Unsort the image using the pixel map. Pixels outside of every span, for
+// example where the mask is black, map to themselves and stay in place. The
+// image must be at least as large as the map, any more of it is unused.
+pub fn pixel_unsort(img: &DynamicImage, px_map: &PixelMap) -> RgbaImage {
+    let (width, height) = (px_map.width as u32, px_map.height as u32);
+    assert!(
+        img.width() >= width && img.height() >= height,
+        "a {}x{} image is smaller than the {width}x{height} pixel map",
+        img.width(),
+        img.height()
+    );
+    let src = img.to_rgba8();
+    let stride = src.width() as usize;
+    let mut out_image = RgbaImage::new(width, height);
+    out_image
+        .par_chunks_exact_mut(4)
+        .zip(px_map.data.par_iter())
+        .for_each(|(p, &i)| {
+            let (x, y) = (i as usize % px_map.width, i as usize / px_map.width);
+            let i = 4 * (y * stride + x);
+            p.copy_from_slice(&src.as_raw()[i..i + 4]);
+        });
+    out_image
+}
+
+// Move each pixel of the image to its location in the pixel map, the inverse
+// of pixel_unsort. The image must be the size of the map.
+fn sort_with_map(img: &DynamicImage, px_map: &PixelMap) -> RgbaImage {
+    assert_eq!(
+        img.dimensions(),
+        (px_map.width as u32, px_map.height as u32)
+    );
+    let src = img.to_rgba8();
+    let mut data = src.as_raw().clone();
+    for (p, &i) in src.chunks_exact(4).zip(px_map.data.iter()) {
+        let i = 4 * i as usize;
+        data[i..i + 4].copy_from_slice(p);
+    }
+    RgbaImage::from_raw(src.width(), src.height(), data).unwrap()
+}
+
+// The lines to sort along for the sort directions that are not rows or columns.
+fn sort_lines(img: &DynamicImage, dir: SortBy) -> Vec<Line> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    match dir {
+        SortBy::Angle(degrees) => angle_lines(width, height, degrees),
+        SortBy::Radial { cx, cy } => radial_lines(width, height, cx, cy),
+        SortBy::Rings { cx, cy } => ring_lines(width, height, cx, cy),
+        SortBy::Hilbert => hilbert_lines(width, height),
+        SortBy::ZOrder => z_order_lines(width, height),
+        SortBy::Global => global_lines(width, height),
+        SortBy::Flow(FlowField::Noise { seed, scale }) => {
+            noise_flow_lines(width, height, seed, scale)
         }
+        SortBy::Flow(FlowField::Gradient) => gradient_flow_lines(img),
+        SortBy::Spiral { inward } => spiral_lines(width, height, inward),
+        _ => unreachable!("{dir:?} is sorted by rows and columns"),
     }
-    out_image
+}
+
+// Sort by rows then columns, or by columns then rows. The second pass finds
+// the spans with the keys, edges and mask of the original image at the
+// source of each pixel, so sorting and unsorting agree.
+fn pixel_map_twice<F: SortKeyFn + ?Sized>(
+    keys: &KeyBuffer<'_, F>,
+    dir: SortBy,
+    row_sort_order: SortOrder,
+    col_sort_order: SortOrder,
+    seg: &Segmenter,
+) -> PixelMap {
+    if dir == SortBy::ColRow {
+        let pm = pixel_map_column(keys, col_sort_order, seg, None);
+        pixel_map_row(keys, row_sort_order, seg, Some(pm))
+    } else {
+        let pm = pixel_map_row(keys, row_sort_order, seg, None);
+        pixel_map_column(keys, col_sort_order, seg, Some(pm))
+    }
+}
+
+// Pixel sort an image in the given sort direction.
+pub fn pixel_sort<F: SortKeyFn + ?Sized>(
+    img: &DynamicImage,
+    dir: SortBy,
+    f: &F,
+    row_sort_order: SortOrder,
+    col_sort_order: SortOrder,
+    span: SpanMode,
+    mask: Option<&GrayImage>,
+) -> RgbaImage {
+    let seg = Segmenter::new(img, span, mask);
+    match dir {
+        SortBy::Row => pixel_sort_row(img, &KeyBuffer::new(img, f), row_sort_order, &seg),
+        SortBy::Column => pixel_sort_column(img, &KeyBuffer::new(img, f), col_sort_order, &seg),
+        SortBy::RowCol | SortBy::ColRow => {
+            let keys = KeyBuffer::new(img, f);
+            let px_map = pixel_map_twice(&keys, dir, row_sort_order, col_sort_order, &seg);
+            sort_with_map(img, &px_map)
+        }
+        SortBy::Angle(_)
+        | SortBy::Radial { .. }
+        | SortBy::Rings { .. }
+        | SortBy::Hilbert
+        | SortBy::ZOrder
+        | SortBy::Global
+        | SortBy::Flow(_)
+        | SortBy::Spiral { .. } => {
+            let lines = sort_lines(img, dir);
+            sort_along(
+                img,
+                &KeyBuffer::new(img, f),
+                row_sort_order,
+                &seg,
+                Lines::Given(&lines),
+            )
+        }
+        SortBy::Nothing => img.to_rgba8(),
+    }
+}
+
+// Build the pixel map of the sort image for the given sort direction.
+pub fn pixel_map<F: SortKeyFn + ?Sized>(
+    img: &DynamicImage,
+    dir: SortBy,
+    f: &F,
+    row_sort_order: SortOrder,
+    col_sort_order: SortOrder,
+    span: SpanMode,
+    mask: Option<&GrayImage>,
+) -> PixelMap {
+    let seg = Segmenter::new(img, span, mask);
+    match dir {
+        SortBy::Row => pixel_map_row(&KeyBuffer::new(img, f), row_sort_order, &seg, None),
+        SortBy::Column => pixel_map_column(&KeyBuffer::new(img, f), col_sort_order, &seg, None),
+        SortBy::RowCol | SortBy::ColRow => pixel_map_twice(
+            &KeyBuffer::new(img, f),
+            dir,
+            row_sort_order,
+            col_sort_order,
+            &seg,
+        ),
+        SortBy::Angle(_)
+        | SortBy::Radial { .. }
+        | SortBy::Rings { .. }
+        | SortBy::Hilbert
+        | SortBy::ZOrder
+        | SortBy::Global
+        | SortBy::Flow(_)
+        | SortBy::Spiral { .. } => {
+            let lines = sort_lines(img, dir);
+            map_along(
+                &KeyBuffer::new(img, f),
+                row_sort_order,
+                &seg,
+                Lines::Given(&lines),
+                None,
+            )
+        }
+        SortBy::Nothing => PixelMap::identity(img.width() as usize, img.height() as usize),
+    }
+}
+
+// Encode a pixel map as a 16 bit image, the red channel holds the x coordinate
+// and the green channel the y coordinate. An error if either does not fit in
+// 16 bits.
+pub fn map_image(px_map: &PixelMap) -> Result<ImageBuffer<Rgb<u16>, Vec<u16>>, String> {
+    if px_map.width > 1 << 16 || px_map.height > 1 << 16 {
+        return Err(format!(
+            "a {}x{} pixel map does not fit in a 16 bit image",
+            px_map.width, px_map.height
+        ));
+    }
+    Ok(ImageBuffer::from_fn(
+        px_map.width as u32,
+        px_map.height as u32,
+        |x, y| {
+            let (x1, y1) = px_map.get(x as usize, y as usize);
+            Rgb([x1 as u16, y1 as u16, 0])
+        },
+    ))
+}
+
+// Resize a mask to the size of the sort image and convert it to grayscale.
+pub fn resize_mask(mask: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    mask.resize_exact(width, height, FilterType::CatmullRom)
+        .into_luma8()
 }
 
 // Choose between Pixel Sort and Pixel Unsort.
+#[derive(Clone, Copy)]
 pub enum DrawType {
     Sort,
     Unsort,
 }
 
-pub(crate) fn draw(
+pub fn draw<F: SortKeyFn + ?Sized>(
     sort_image: &DynamicImage,
     unsort_image: &DynamicImage,
     dir: SortBy,
-    key: SortKey,
+    f: &F,
     draw_type: DrawType,
     row_sort_order: SortOrder,
     col_sort_order: SortOrder,
+    span: SpanMode,
+    mask: Option<&DynamicImage>,
     pre_sort: bool,
 ) -> RgbaImage {
-    let unsort_image = unsort_image.resize_exact(
-        sort_image.width(),
-        sort_image.height(),
-        FilterType::CatmullRom,
-    );
+    let mask = mask.map(|m| resize_mask(m, sort_image.width(), sort_image.height()));
+    let mask = mask.as_ref();
 
-    let sort_fn = match key {
-        SortKey::Lightness => luma,
-        SortKey::Hue => hue,
-        SortKey::Saturation => sat,
-    };
-    let mut unsort_image = unsort_image;
-    if pre_sort {
-        unsort_image = match dir {
-            SortBy::Row => {
-                DynamicImage::ImageRgba8(pixel_sort_row(&unsort_image, sort_fn, row_sort_order))
-            }
-            SortBy::Column => {
-                DynamicImage::ImageRgba8(pixel_sort_column(&unsort_image, sort_fn, col_sort_order))
-            }
-            SortBy::ColRow => {
-                let temp_img = DynamicImage::ImageRgba8(pixel_sort_column(
+    match draw_type {
+        DrawType::Sort => pixel_sort(
+            sort_image,
+            dir,
+            f,
+            row_sort_order,
+            col_sort_order,
+            span,
+            mask,
+        ),
+        DrawType::Unsort => {
+            let mut unsort_image = unsort_image.resize_exact(
+                sort_image.width(),
+                sort_image.height(),
+                FilterType::CatmullRom,
+            );
+            // Global unsorting gives each pixel the unsort pixel of the same
+            // rank, which only holds if every pixel of both images is ranked.
+            let (span, mask) = match dir {
+                SortBy::Global => (SpanMode::Full, None),
+                _ => (span, mask),
+            };
+            if pre_sort || dir == SortBy::Global {
+                unsort_image = DynamicImage::ImageRgba8(pixel_sort(
                     &unsort_image,
-                    sort_fn,
+                    dir,
+                    f,
+                    row_sort_order,
                     col_sort_order,
+                    span,
+                    mask,
                 ));
-                DynamicImage::ImageRgba8(pixel_sort_row(&temp_img, sort_fn, row_sort_order))
             }
-            SortBy::RowCol => {
-                let temp_img = DynamicImage::ImageRgba8(pixel_sort_row(
-                    &unsort_image,
-                    sort_fn,
-                    row_sort_order,
-                ));
-                DynamicImage::ImageRgba8(pixel_sort_column(&temp_img, sort_fn, col_sort_order))
+            let px_map = pixel_map(
+                sort_image,
+                dir,
+                f,
+                row_sort_order,
+                col_sort_order,
+                span,
+                mask,
+            );
+            pixel_unsort(&unsort_image, &px_map)
+        }
+    }
+}
+
+// Sort the lines of a strip of an image. The lines are in the coordinates of
+// the strip, `corner` is its top left corner in the whole image and `first`
+// the index of its first line, so the spans match those of the whole image.
+fn strip_orders<F: SortKeyFn + ?Sized>(
+    strip: &DynamicImage,
+    f: &F,
+    order: SortOrder,
+    seg: &Segmenter,
+    lines: &[Line],
+    corner: (usize, usize),
+    first: usize,
+) -> Vec<Vec<usize>> {
+    let keys = KeyBuffer::new(strip, f);
+    lines
+        .par_iter()
+        .enumerate()
+        .map(|(n, line)| {
+            let coords = line
+                .iter()
+                .map(|(x, y)| (x + corner.0, y + corner.1))
+                .collect::<Vec<_>>();
+            let line_keys = keys.line(line);
+            sort_order(
+                &line_keys,
+                &seg.spans(&line_keys, &coords, first + n),
+                order,
+            )
+        })
+        .collect()
+}
+
+// Move the pixels of a strip along its lines to their sorted positions, or
+// with `unsort` take each pixel from where the sort would move it.
+fn move_pixels(
+    src: &RgbaImage,
+    lines: &[Line],
+    orders: Vec<Vec<usize>>,
+    unsort: bool,
+) -> RgbaImage {
+    let mut out = src.clone();
+    for (line, sorted) in lines.iter().zip(orders) {
+        for (i, j) in sorted.into_iter().enumerate() {
+            let (to, from) = if unsort {
+                (line[j], line[i])
+            } else {
+                (line[i], line[j])
+            };
+            out.put_pixel(
+                to.0 as u32,
+                to.1 as u32,
+                *src.get_pixel(from.0 as u32, from.1 as u32),
+            );
+        }
+    }
+    out
+}
+
+// Draw one strip at a time to bound the memory used on very large images.
+// Row sorts are split into bands of `strip` rows and column sorts into bands
+// of `strip` columns. Each band is passed to `emit` with its top left corner
+// as soon as it is done, row bands in order from the top. No pixel map of
+// the whole image is built and the edges and mask are found one band at a
+// time, the output is the same as `draw`. An unsort image or mask that is
+// not the size of the sort image is resized whole first. Only row, column
+// and nothing sorts can be split.
+pub fn draw_strips<F, E>(
+    sort_image: &DynamicImage,
+    unsort_image: &DynamicImage,
+    dir: SortBy,
+    f: &F,
+    draw_type: DrawType,
+    row_sort_order: SortOrder,
+    col_sort_order: SortOrder,
+    span: SpanMode,
+    mask: Option<&DynamicImage>,
+    pre_sort: bool,
+    strip: u32,
+    mut emit: E,
+) -> Result<(), String>
+where
+    F: SortKeyFn + ?Sized,
+    E: FnMut(u32, u32, RgbaImage),
+{
+    let order = match dir {
+        SortBy::Row | SortBy::Nothing => row_sort_order,
+        SortBy::Column => col_sort_order,
+        _ => return Err(format!("{dir:?} sorts can not be drawn in strips")),
+    };
+    let (width, height) = sort_image.dimensions();
+    let resized;
+    let unsort_image = if unsort_image.dimensions() == (width, height) {
+        unsort_image
+    } else {
+        resized = unsort_image.resize_exact(width, height, FilterType::CatmullRom);
+        &resized
+    };
+    let resized_mask;
+    let mask = match mask {
+        Some(m) if m.dimensions() != (width, height) => {
+            resized_mask = DynamicImage::ImageLuma8(resize_mask(m, width, height));
+            Some(&resized_mask)
+        }
+        m => m,
+    };
+    let pre_sort = matches!(draw_type, DrawType::Unsort) && pre_sort;
+
+    let columns = dir == SortBy::Column;
+    let extent = if columns { width } else { height };
+    let step = strip.max(1);
+    for start in (0..extent).step_by(step as usize) {
+        let size = step.min(extent - start);
+        let rect = if columns {
+            (start, 0, size, height)
+        } else {
+            (0, start, width, size)
+        };
+        let (x0, y0, w, h) = rect;
+        let lines = match dir {
+            SortBy::Row => row_lines(w as usize, h as usize),
+            SortBy::Column => column_lines(w as usize, h as usize),
+            _ => Vec::new(),
+        };
+        let corner = (x0 as usize, y0 as usize);
+        let strip_mask = mask.map(|m| m.crop_imm(x0, y0, w, h).into_luma8());
+        let seg = Segmenter::region(sort_image, span, strip_mask.as_ref(), rect);
+        let sort_strip = sort_image.crop_imm(x0, y0, w, h);
+        let orders = strip_orders(&sort_strip, f, order, &seg, &lines, corner, start as usize);
+        let out = match draw_type {
+            DrawType::Sort => move_pixels(&sort_strip.to_rgba8(), &lines, orders, false),
+            DrawType::Unsort => {
+                let mut unsort_strip = unsort_image.crop_imm(x0, y0, w, h).to_rgba8();
+                if pre_sort {
+                    let seg = Segmenter::region(unsort_image, span, strip_mask.as_ref(), rect);
+                    let strip = DynamicImage::ImageRgba8(unsort_strip);
+                    let pre = strip_orders(&strip, f, order, &seg, &lines, corner, start as usize);
+                    unsort_strip = move_pixels(&strip.to_rgba8(), &lines, pre, false);
+                }
+                move_pixels(&unsort_strip, &lines, orders, true)
             }
-            SortBy::Nothing => unsort_image,
         };
-    };
+        emit(x0, y0, out);
+    }
+    Ok(())
+}
 
-    let px_map = match dir {
-        SortBy::Row => pixel_map_row(sort_image, sort_fn, row_sort_order, None),
-        SortBy::Column => pixel_map_column(sort_image, sort_fn, col_sort_order, None),
-        SortBy::RowCol => {
-            let pm = pixel_map_row(sort_image, sort_fn, row_sort_order, None);
-            pixel_map_column(sort_image, sort_fn, col_sort_order, Some(pm))
-        }
-        SortBy::ColRow => {
-            let pm = pixel_map_column(sort_image, sort_fn, col_sort_order, None);
-            pixel_map_row(sort_image, sort_fn, row_sort_order, Some(pm))
-        }
-        SortBy::Nothing => Matrix::generate(
-            sort_image.width() as usize,
-            sort_image.height() as usize,
-            |x, y| (x, y),
-        ),
-    };
-    match draw_type {
-        DrawType::Sort => match dir {
-            SortBy::Row => pixel_sort_row(sort_image, sort_fn, row_sort_order),
-            SortBy::Column => pixel_sort_column(sort_image, sort_fn, col_sort_order),
-            SortBy::RowCol => {
-                let row_sort = pixel_sort_row(sort_image, sort_fn, row_sort_order);
-                pixel_sort_column(&DynamicImage::ImageRgba8(row_sort), sort_fn, col_sort_order)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            let v = ((x * 37 + y * 101) % 251) as u8;
+            Rgba([v, v.wrapping_mul(3), 255 - v, 255])
+        }))
+    }
+
+    #[test]
+    fn random_roundtrip_test() {
+        // Unsorting the sorted image with the pixel map gives back the
+        // original only if both use the same spans.
+        let img = test_image(40, 30);
+        let random = SpanMode::Random {
+            min: 3,
+            max: 12,
+            skip: 0.3,
+            seed: 7,
+        };
+        let edge = SpanMode::Edge { threshold: 100 };
+        for (dir, span) in [
+            SortBy::Row,
+            SortBy::Column,
+            SortBy::RowCol,
+            SortBy::ColRow,
+            SortBy::Angle(30.0),
+            SortBy::Radial { cx: 0.3, cy: 0.6 },
+            SortBy::Rings { cx: 0.5, cy: 0.5 },
+            SortBy::Hilbert,
+            SortBy::ZOrder,
+            SortBy::Global,
+            SortBy::Flow(FlowField::Noise {
+                seed: 1,
+                scale: 0.05,
+            }),
+            SortBy::Flow(FlowField::Gradient),
+            SortBy::Spiral { inward: false },
+        ]
+        .into_iter()
+        .map(|dir| (dir, random))
+        .chain([(SortBy::RowCol, edge), (SortBy::ColRow, edge)])
+        {
+            let sorted = pixel_sort(
+                &img,
+                dir,
+                &hue,
+                SortOrder::Ascending,
+                SortOrder::Descending,
+                span,
+                None,
+            );
+            let px_map = pixel_map(
+                &img,
+                dir,
+                &hue,
+                SortOrder::Ascending,
+                SortOrder::Descending,
+                span,
+                None,
+            );
+            let out = pixel_unsort(&DynamicImage::ImageRgba8(sorted), &px_map);
+            assert_eq!(out, img.to_rgba8());
+        }
+    }
+
+    #[test]
+    fn rows_then_columns_test() {
+        // Away from edges sorting by rows then columns is the same as sorting
+        // the rows and then sorting the columns of the result.
+        let img = test_image(40, 30);
+        let span = SpanMode::Random {
+            min: 3,
+            max: 12,
+            skip: 0.3,
+            seed: 7,
+        };
+        let seg = Segmenter::new(&img, span, None);
+        let rows = pixel_sort_row(
+            &img,
+            &KeyBuffer::new(&img, &hue),
+            SortOrder::Ascending,
+            &seg,
+        );
+        let rows = DynamicImage::ImageRgba8(rows);
+        let keys = KeyBuffer::new(&rows, &hue);
+        assert_eq!(
+            pixel_sort(
+                &img,
+                SortBy::RowCol,
+                &hue,
+                SortOrder::Ascending,
+                SortOrder::Descending,
+                span,
+                None,
+            ),
+            pixel_sort_column(&rows, &keys, SortOrder::Descending, &seg)
+        );
+    }
+
+    #[test]
+    fn angle_matches_rows_test() {
+        let img = test_image(20, 10);
+        let seg = Segmenter::new(&img, SpanMode::Full, None);
+        let keys = KeyBuffer::new(&img, &luma);
+        let lines = angle_lines(20, 10, 0.0);
+        assert_eq!(
+            pixel_sort_lines(&img, &keys, SortOrder::Ascending, &seg, &lines).unwrap(),
+            pixel_sort_row(&img, &keys, SortOrder::Ascending, &seg)
+        );
+        assert_eq!(
+            pixel_map_lines(&keys, SortOrder::Ascending, &seg, &lines, None).unwrap(),
+            pixel_map_row(&keys, SortOrder::Ascending, &seg, None)
+        );
+    }
+
+    #[test]
+    fn global_unsort_test() {
+        // The brightest pixel of the sort image gets the brightest pixel of
+        // the unsort image and so on down, spans are ignored.
+        let sort_img = test_image(16, 12);
+        let unsort_img = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 12, |x, y| {
+            let v = ((x * 7 + y * 13) % 256) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        let out = draw(
+            &sort_img,
+            &unsort_img,
+            SortBy::Global,
+            &luma,
+            DrawType::Unsort,
+            SortOrder::Ascending,
+            SortOrder::Ascending,
+            SpanMode::Threshold {
+                lower: 0,
+                upper: 100,
+            },
+            None,
+            false,
+        );
+        let mut pairs = sort_img
+            .pixels()
+            .map(|(x, y, p)| (luma(p), luma(*out.get_pixel(x, y))))
+            .collect::<Vec<_>>();
+        pairs.sort();
+        assert!(pairs.windows(2).all(|w| w[0].1 <= w[1].1));
+        let mut expected = unsort_img
+            .pixels()
+            .map(|(_, _, p)| luma(p))
+            .collect::<Vec<_>>();
+        let mut actual = pairs.iter().map(|p| p.1).collect::<Vec<_>>();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn custom_lines_test() {
+        // Sort the left and right halves of a row as separate lines, the
+        // right half backwards.
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(6, 1, |x, _| {
+            let v = [30, 10, 20, 60, 40, 50][x as usize];
+            Rgba([v, v, v, 255])
+        }));
+        let seg = Segmenter::new(&img, SpanMode::Full, None);
+        let keys = KeyBuffer::new(&img, &luma);
+        let lines = vec![vec![(0, 0), (1, 0), (2, 0)], vec![(5, 0), (4, 0), (3, 0)]];
+        let sorted = pixel_sort_lines(&img, &keys, SortOrder::Ascending, &seg, &lines).unwrap();
+        let values = sorted.pixels().map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!(values, vec![10, 20, 30, 60, 50, 40]);
+        let px_map = pixel_map_lines(&keys, SortOrder::Ascending, &seg, &lines, None).unwrap();
+        assert_eq!(
+            pixel_unsort(&DynamicImage::ImageRgba8(sorted), &px_map),
+            img.to_rgba8()
+        );
+        // Lines that overlap or leave the image are an error.
+        let overlap = vec![vec![(0, 0), (1, 0)], vec![(1, 0)]];
+        assert!(pixel_sort_lines(&img, &keys, SortOrder::Ascending, &seg, &overlap).is_err());
+        let outside = vec![vec![(6, 0)]];
+        assert!(pixel_map_lines(&keys, SortOrder::Ascending, &seg, &outside, None).is_err());
+        // So are maps of the wrong size or that send two pixels to one place.
+        let grid = Some(PixelMap::identity(5, 1));
+        assert!(pixel_map_lines(&keys, SortOrder::Ascending, &seg, &lines, grid).is_err());
+        let mut grid = PixelMap::identity(6, 1);
+        grid.set(0, 0, (1, 0));
+        assert!(pixel_map_lines(&keys, SortOrder::Ascending, &seg, &lines, Some(grid)).is_err());
+    }
+
+    #[test]
+    fn counting_sort_rows_test() {
+        // Luma keys are counting sorted, shifting them by a half forces the
+        // comparison sort, the order is the same.
+        let img = test_image(64, 8);
+        let seg = Segmenter::new(&img, SpanMode::Full, None);
+        let shifted = |c: Rgba<u8>| Key(luma(c).0 + 0.5);
+        for order in [SortOrder::Ascending, SortOrder::Descending] {
+            let counting = KeyBuffer::new(&img, &luma);
+            let comparison = KeyBuffer::new(&img, &shifted);
+            assert_eq!(
+                pixel_sort_row(&img, &counting, order, &seg),
+                pixel_sort_row(&img, &comparison, order, &seg)
+            );
+            assert_eq!(
+                pixel_map_row(&counting, order, &seg, None),
+                pixel_map_row(&comparison, order, &seg, None)
+            );
+        }
+    }
+
+    #[test]
+    fn strips_test() {
+        let sort_img = test_image(40, 30);
+        let unsort_img = DynamicImage::ImageRgba8(RgbaImage::from_fn(50, 20, |x, y| {
+            Rgba([(x * 5) as u8, (y * 12) as u8, (x ^ y) as u8, 255])
+        }));
+        let mask = DynamicImage::ImageLuma8(GrayImage::from_fn(40, 30, |x, y| {
+            Luma([if (x + y) % 9 < 6 { 255 } else { 0 }])
+        }));
+        let span = SpanMode::Random {
+            min: 3,
+            max: 12,
+            skip: 0.3,
+            seed: 7,
+        };
+        let edge = SpanMode::Edge { threshold: 100 };
+        for (dir, span) in [
+            (SortBy::Row, span),
+            (SortBy::Column, span),
+            (SortBy::Nothing, span),
+            (SortBy::Row, edge),
+            (SortBy::Column, edge),
+        ] {
+            for (draw_type, pre_sort) in [
+                (DrawType::Sort, false),
+                (DrawType::Unsort, false),
+                (DrawType::Unsort, true),
+            ] {
+                let whole = draw(
+                    &sort_img,
+                    &unsort_img,
+                    dir,
+                    &hue,
+                    draw_type,
+                    SortOrder::Descending,
+                    SortOrder::Ascending,
+                    span,
+                    Some(&mask),
+                    pre_sort,
+                );
+                let mut out = RgbaImage::new(40, 30);
+                draw_strips(
+                    &sort_img,
+                    &unsort_img,
+                    dir,
+                    &hue,
+                    draw_type,
+                    SortOrder::Descending,
+                    SortOrder::Ascending,
+                    span,
+                    Some(&mask),
+                    pre_sort,
+                    7,
+                    |x, y, strip| imageops::replace(&mut out, &strip, x as i64, y as i64),
+                )
+                .unwrap();
+                assert_eq!(out, whole);
             }
-            SortBy::ColRow => {
-                let col_sort = pixel_sort_column(sort_image, sort_fn, col_sort_order);
-                pixel_sort_row(&DynamicImage::ImageRgba8(col_sort), sort_fn, row_sort_order)
+        }
+        let result = draw_strips(
+            &sort_img,
+            &sort_img,
+            SortBy::Hilbert,
+            &hue,
+            DrawType::Sort,
+            SortOrder::Ascending,
+            SortOrder::Ascending,
+            SpanMode::Full,
+            None,
+            false,
+            7,
+            |_, _, _| {},
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn closure_key_test() {
+        // Sort by distance from a captured red value.
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(5, 1, |x, _| {
+            Rgba([[0, 100, 140, 90, 255][x as usize], 0, 0, 255])
+        }));
+        let target = 120u8;
+        let near = move |c: Rgba<u8>| Key::from(c[0].abs_diff(target));
+        let sorted = pixel_sort(
+            &img,
+            SortBy::Row,
+            &near,
+            SortOrder::Ascending,
+            SortOrder::Ascending,
+            SpanMode::Full,
+            None,
+        );
+        let values = sorted.pixels().map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!(values, vec![100, 140, 90, 0, 255]);
+    }
+
+    #[test]
+    fn unsort_larger_image_test() {
+        // Only the top left of an image larger than the map is used.
+        let mut px_map = PixelMap::identity(4, 2);
+        px_map.set(0, 0, (3, 1));
+        px_map.set(3, 1, (0, 0));
+        let img = test_image(5, 2);
+        let out = pixel_unsort(&img, &px_map);
+        assert_eq!(out.dimensions(), (4, 2));
+        assert_eq!(*out.get_pixel(0, 0), img.get_pixel(3, 1));
+        assert_eq!(*out.get_pixel(3, 1), img.get_pixel(0, 0));
+        assert_eq!(*out.get_pixel(0, 1), img.get_pixel(0, 1));
+    }
+
+    #[test]
+    fn map_image_test() {
+        let mut px_map = PixelMap::identity(3, 2);
+        px_map.set(0, 0, (2, 1));
+        let img = map_image(&px_map).unwrap();
+        assert_eq!(*img.get_pixel(0, 0), Rgb([2, 1, 0]));
+        assert_eq!(*img.get_pixel(1, 1), Rgb([1, 1, 0]));
+        assert!(map_image(&PixelMap::identity(70000, 1)).is_err());
+    }
+
+    #[test]
+    fn mask_unsort_test() {
+        let sort_img = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| {
+            let v = (255 - 16 * (x + 4 * y)) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        let unsort_img = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| {
+            Rgba([x as u8, y as u8, 0, 255])
+        }));
+        let mask = GrayImage::from_fn(4, 4, |x, _| Luma([if x < 2 { 255 } else { 0 }]));
+        let px_map = pixel_map(
+            &sort_img,
+            SortBy::Row,
+            &luma,
+            SortOrder::Ascending,
+            SortOrder::Ascending,
+            SpanMode::Full,
+            Some(&mask),
+        );
+        let out = pixel_unsort(&unsort_img, &px_map);
+        for y in 0..4 {
+            assert_eq!(*out.get_pixel(0, y), unsort_img.get_pixel(1, y));
+            assert_eq!(*out.get_pixel(1, y), unsort_img.get_pixel(0, y));
+            for x in 2..4 {
+                assert_eq!(*out.get_pixel(x, y), unsort_img.get_pixel(x, y));
             }
-            SortBy::Nothing => pixel_unsort(sort_image, &px_map),
-        },
-        DrawType::Unsort => pixel_unsort(&unsort_image, &px_map),
+        }
     }
 }
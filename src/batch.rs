@@ -0,0 +1,175 @@
+//! Apply the same drawing to every image in a directory or glob.
+
+use image::{DynamicImage, RgbaImage};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// The outcome of processing a single input file.
+#[derive(Debug)]
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub output: Result<PathBuf, String>,
+}
+
+/// List the images to process. A directory yields every png or jpeg file
+/// directly inside it, anything else is treated as a glob pattern.
+pub fn collect_inputs(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(pattern);
+    let mut inputs = if path.is_dir() {
+        path.read_dir()
+            .map_err(|e| format!("{pattern}: {e}"))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && is_image(p))
+            .collect::<Vec<_>>()
+    } else {
+        glob::glob(pattern)
+            .map_err(|e| format!("{pattern}: {e}"))?
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .collect::<Vec<_>>()
+    };
+    inputs.sort();
+    Ok(inputs)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Build the output path from a naming template. The placeholders `{stem}`,
+/// `{ext}` and `{index}` are replaced by the input file stem, the input file
+/// extension and the position of the input in the batch.
+pub fn output_path(template: &str, out_dir: &Path, input: &Path, index: usize) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = input.extension().unwrap_or_default().to_string_lossy();
+    let name = template
+        .replace("{stem}", &stem)
+        .replace("{ext}", &ext)
+        .replace("{index}", &index.to_string());
+    out_dir.join(name)
+}
+
+/// The output path of every input. It is an error if two inputs would be
+/// written to the same file, for example `photo.png` and `photo.jpg` named
+/// `{stem}.png`, or if an output would overwrite any of the inputs.
+pub fn output_paths(
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    template: &str,
+) -> Result<Vec<PathBuf>, String> {
+    let outputs = inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| output_path(template, out_dir, input, i))
+        .collect::<Vec<_>>();
+    let inputs_at = inputs
+        .iter()
+        .map(|input| (resolve(input), input))
+        .collect::<HashMap<_, _>>();
+    let mut seen = HashMap::new();
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let at = resolve(output);
+        if let Some(other) = inputs_at.get(&at) {
+            return Err(format!(
+                "{} would overwrite the input {}",
+                output.display(),
+                other.display()
+            ));
+        }
+        if let Some(other) = seen.insert(at, input) {
+            return Err(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            ));
+        }
+    }
+    Ok(outputs)
+}
+
+// The path with its directory made absolute and free of links, so different
+// ways of naming the same file compare equal. The file need not exist.
+fn resolve(path: &Path) -> PathBuf {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match (dir.canonicalize(), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Run `f` on every input in parallel and save the results. Nothing is run if
+/// two inputs would be saved to the same file. A failure on one file,
+/// including an error from `f`, is recorded in its result and does not stop
+/// the others.
+pub fn batch<F>(
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    template: &str,
+    f: F,
+) -> Result<Vec<BatchResult>, String>
+where
+    F: Fn(&DynamicImage) -> Result<RgbaImage, String> + Sync,
+{
+    let outputs = output_paths(inputs, out_dir, template)?;
+    Ok(inputs
+        .par_iter()
+        .zip(outputs)
+        .map(|(input, output)| {
+            let result = image::open(input)
+                .map_err(|e| e.to_string())
+                .and_then(|img| f(&img))
+                .and_then(|out| out.save(&output).map_err(|e| e.to_string()))
+                .map(|_| output);
+            BatchResult {
+                input: input.clone(),
+                output: result,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_path_test() {
+        let p = output_path(
+            "{stem}_{index}.{ext}",
+            Path::new("out"),
+            Path::new("in/photo.jpg"),
+            3,
+        );
+        assert_eq!(p, PathBuf::from("out/photo_3.jpg"));
+    }
+
+    #[test]
+    fn output_paths_test() {
+        let inputs = [PathBuf::from("in/photo.png"), PathBuf::from("in/photo.jpg")];
+        assert!(output_paths(&inputs, Path::new("out"), "{stem}_sorted.png").is_err());
+        assert_eq!(
+            output_paths(&inputs, Path::new("out"), "{stem}.{ext}"),
+            Ok(vec![
+                PathBuf::from("out/photo.png"),
+                PathBuf::from("out/photo.jpg")
+            ])
+        );
+    }
+
+    #[test]
+    fn is_image_test() {
+        assert!(is_image(Path::new("a/b.PNG")));
+        assert!(!is_image(Path::new("a/b.txt")));
+        assert!(!is_image(Path::new("a/b")));
+    }
+}
@@ -0,0 +1,446 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+use clap::{Args, Parser, Subcommand};
+use image::{imageops, DynamicImage, RgbaImage};
+use pixel_unsort::art::*;
+use pixel_unsort::batch::*;
+use pixel_unsort::core::*;
+use pixel_unsort::sortfns::{composite_fn, key_fn, SortKeyFn};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Pixel sort and unsort images from the command line.
+#[derive(Parser)]
+#[command(name = "pixel-unsort", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pixel sort an image.
+    Sort {
+        /// The image to sort.
+        image: PathBuf,
+        /// Output file, the format is taken from the extension.
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        opts: SortOpts,
+    },
+    /// Unsort an image using the pixel map of the sort image.
+    Unsort {
+        /// The image that determines the pixel map.
+        sort_image: PathBuf,
+        /// The image whose pixels are rearranged.
+        unsort_image: PathBuf,
+        /// Output file, the format is taken from the extension.
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        opts: SortOpts,
+    },
+    /// Write the pixel map of an image as a 16 bit png, x in red and y in green.
+    Map {
+        /// The image that determines the pixel map.
+        image: PathBuf,
+        /// Output file, the format is taken from the extension.
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        opts: SortOpts,
+    },
+    /// Sort, or unsort against one palette image, every image in a directory.
+    Batch {
+        /// A directory of png and jpeg files, or a glob pattern.
+        input: String,
+        /// Directory to write the results to.
+        #[arg(short = 'd', long, default_value = ".")]
+        out_dir: PathBuf,
+        /// Output file name, `{stem}`, `{ext}` and `{index}` are replaced
+        /// by the input file stem, extension and position in the batch
+        /// [default: {stem}_sorted.png, or {stem}_unsorted.png with --unsort].
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Unsort every input using the pixels of this image instead of
+        /// sorting it.
+        #[arg(short, long)]
+        unsort: Option<PathBuf>,
+        #[command(flatten)]
+        opts: SortOpts,
+    },
+}
+
+#[derive(Args)]
+struct SortOpts {
+    /// Sort direction: row, column, rowcol, colrow, nothing, diagonal,
+    /// angle:DEGREES, radial[:CX,CY], rings[:CX,CY] with the centre in
+    /// normalized coordinates, hilbert, zorder, global, flow[:SEED,SCALE] for
+    /// noise streamlines, flow:gradient or spiral[:in|:out]. Everything but
+    /// columns uses the row order.
+    #[arg(short, long, default_value = "row")]
+    by: SortBy,
+    /// Sort key: lightness, hue, saturation, red, green, blue, alpha, max,
+    /// min, oklightness, okchroma, okhue, labl, laba, labb, or distance and
+    /// labdistance for the OKLab and CIELAB distance from the reference.
+    /// hue:DEGREES starts the hue at DEGREES instead of red and hue:auto at
+    /// the largest gap in the hues of each line.
+    #[arg(short, long, default_value = "lightness")]
+    key: SortKey,
+    /// Reference colour for the distance keys as a hex RRGGBB, the leading
+    /// `#` is optional. Required if any key is a distance.
+    #[arg(long, value_parser = parse_hex)]
+    reference: Option<[u8; 3]>,
+    /// Break ties in the sort key with this key, give it twice for a third.
+    #[arg(long, value_name = "KEY")]
+    then: Vec<SortKey>,
+    /// Sort by a weighted sum of keys instead of the sort key, for example
+    /// `--blend lightness=0.7 --blend hue=0.3`.
+    #[arg(long, value_name = "KEY=WEIGHT", value_parser = parse_weighted, conflicts_with = "then")]
+    blend: Vec<(SortKey, f32)>,
+    /// Row sort order: ascending or descending.
+    #[arg(long, default_value = "ascending")]
+    row_order: SortOrder,
+    /// Column sort order: ascending or descending.
+    #[arg(long, default_value = "ascending")]
+    col_order: SortOrder,
+    /// Only sort runs of pixels whose key lies between LOWER and UPPER.
+    #[arg(long, num_args = 2, value_names = ["LOWER", "UPPER"])]
+    threshold: Option<Vec<i16>>,
+    /// Break spans at pixels whose luma gradient magnitude is at least EDGE.
+    #[arg(long, conflicts_with = "threshold")]
+    edge: Option<u16>,
+    /// Sort random length spans between MIN and MAX pixels long.
+    #[arg(long, num_args = 2, value_names = ["MIN", "MAX"], conflicts_with_all = ["threshold", "edge"])]
+    random: Option<Vec<usize>>,
+    /// Probability of leaving a random span unsorted, from 0 to 1.
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability, requires = "random")]
+    skip: f32,
+    /// Seed for the random spans, the same seed gives the same spans.
+    #[arg(long, default_value_t = 0, requires = "random")]
+    seed: u64,
+    /// Grayscale mask, only pixels where the mask is at least half white are
+    /// sorted. It is resized to the size of the sort image.
+    #[arg(long)]
+    mask: Option<PathBuf>,
+    /// Pixel sort the unsort image before unsorting.
+    #[arg(long)]
+    pre_sort: bool,
+    /// Draw bands of STRIP rows or columns at a time to bound the memory used
+    /// on very large images, only for row and column sorts. Row sorts saved
+    /// as png are written to the file band by band. An unsort image or mask
+    /// of a different size than the sort image is still resized whole.
+    #[arg(long)]
+    strip: Option<u32>,
+}
+
+impl SortOpts {
+    fn with_reference(&self, key: SortKey) -> Result<SortKey, String> {
+        match (key, self.reference) {
+            (SortKey::Distance { space, .. }, Some(color)) => {
+                Ok(SortKey::Distance { color, space })
+            }
+            (SortKey::Distance { .. }, None) => {
+                Err("the distance keys need a --reference colour".to_string())
+            }
+            (key, _) => Ok(key),
+        }
+    }
+
+    fn sort_fn(&self) -> Result<Box<dyn SortKeyFn>, String> {
+        if !self.blend.is_empty() {
+            let keys = self
+                .blend
+                .iter()
+                .map(|(k, w)| Ok((self.with_reference(*k)?, *w)))
+                .collect::<Result<_, String>>()?;
+            return Ok(composite_fn(&Composite::Blend(keys)));
+        }
+        if self.then.len() > 2 {
+            return Err("at most two tie-break keys can be given".to_string());
+        }
+        let key = self.with_reference(self.key)?;
+        if self.then.is_empty() {
+            return Ok(key_fn(key));
+        }
+        let mut keys = vec![key];
+        for k in &self.then {
+            keys.push(self.with_reference(*k)?);
+        }
+        Ok(composite_fn(&Composite::TieBreak(keys)))
+    }
+
+    fn span(&self) -> SpanMode {
+        if let Some([lower, upper]) = self.threshold.as_deref() {
+            SpanMode::Threshold {
+                lower: *lower,
+                upper: *upper,
+            }
+        } else if let Some(threshold) = self.edge {
+            SpanMode::Edge { threshold }
+        } else if let Some([min, max]) = self.random.as_deref() {
+            SpanMode::Random {
+                min: *min,
+                max: *max,
+                skip: self.skip,
+                seed: self.seed,
+            }
+        } else {
+            SpanMode::Full
+        }
+    }
+
+    fn mask(&self) -> Result<Option<DynamicImage>, String> {
+        self.mask.as_deref().map(open).transpose()
+    }
+}
+
+fn parse_hex(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let err = || format!("not a hex colour: {s}");
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(err());
+    }
+    let channel =
+        |i: usize| u8::from_str_radix(hex.get(i..i + 2).ok_or_else(err)?, 16).map_err(|_| err());
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn parse_probability(s: &str) -> Result<f32, String> {
+    match s.trim().parse() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("not a probability from 0 to 1: {s}")),
+    }
+}
+
+fn parse_weighted(s: &str) -> Result<(SortKey, f32), String> {
+    let (key, weight) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=WEIGHT: {s}"))?;
+    let weight = weight
+        .trim()
+        .parse()
+        .map_err(|_| format!("not a weight: {weight}"))?;
+    Ok((key.trim().parse()?, weight))
+}
+
+fn open(path: &Path) -> Result<DynamicImage, String> {
+    image::open(path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn render(
+    sort_image: &DynamicImage,
+    unsort_image: &DynamicImage,
+    mask: Option<&DynamicImage>,
+    draw_type: DrawType,
+    f: &dyn SortKeyFn,
+    opts: &SortOpts,
+) -> Result<RgbaImage, String> {
+    let global = matches!(draw_type, DrawType::Unsort) && opts.by == SortBy::Global;
+    if global && (opts.span() != SpanMode::Full || mask.is_some()) {
+        return Err("global unsorting ranks every pixel, it takes no spans or mask".to_string());
+    }
+    let Some(strip) = opts.strip else {
+        return Ok(draw(
+            sort_image,
+            unsort_image,
+            opts.by,
+            f,
+            draw_type,
+            opts.row_order,
+            opts.col_order,
+            opts.span(),
+            mask,
+            opts.pre_sort,
+        ));
+    };
+    let mut out = RgbaImage::new(sort_image.width(), sort_image.height());
+    draw_strips(
+        sort_image,
+        unsort_image,
+        opts.by,
+        f,
+        draw_type,
+        opts.row_order,
+        opts.col_order,
+        opts.span(),
+        mask,
+        opts.pre_sort,
+        strip,
+        |x, y, s| imageops::replace(&mut out, &s, x as i64, y as i64),
+    )?;
+    Ok(out)
+}
+
+// Render straight into the output file. Row strips of a png are written as
+// they are drawn, everything else is drawn in memory and then saved.
+fn render_to(
+    sort_image: &DynamicImage,
+    unsort_image: &DynamicImage,
+    mask: Option<&DynamicImage>,
+    draw_type: DrawType,
+    f: &dyn SortKeyFn,
+    opts: &SortOpts,
+    path: &Path,
+) -> Result<(), String> {
+    let png = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+    let Some(strip) = opts.strip.filter(|_| png && opts.by == SortBy::Row) else {
+        return save(
+            &render(sort_image, unsort_image, mask, draw_type, f, opts)?,
+            path,
+        );
+    };
+    let err = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+    let file = File::create(path).map_err(|e| err(&e))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        sort_image.width(),
+        sort_image.height(),
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .and_then(|w| w.into_stream_writer())
+        .map_err(|e| err(&e))?;
+    let mut written = Ok(());
+    draw_strips(
+        sort_image,
+        unsort_image,
+        opts.by,
+        f,
+        draw_type,
+        opts.row_order,
+        opts.col_order,
+        opts.span(),
+        mask,
+        opts.pre_sort,
+        strip,
+        |_, _, s| {
+            if written.is_ok() {
+                written = writer.write_all(s.as_raw());
+            }
+        },
+    )?;
+    written.map_err(|e| err(&e))?;
+    writer.finish().map_err(|e| err(&e))
+}
+
+fn save(img: &RgbaImage, path: &Path) -> Result<(), String> {
+    img.save(path)
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    match cli.command {
+        Command::Sort {
+            image,
+            output,
+            opts,
+        } => {
+            let img = open(&image)?;
+            let mask = opts.mask()?;
+            let f = opts.sort_fn()?;
+            render_to(
+                &img,
+                &img,
+                mask.as_ref(),
+                DrawType::Sort,
+                f.as_ref(),
+                &opts,
+                &output,
+            )
+        }
+        Command::Unsort {
+            sort_image,
+            unsort_image,
+            output,
+            opts,
+        } => {
+            let img1 = open(&sort_image)?;
+            let img2 = open(&unsort_image)?;
+            let mask = opts.mask()?;
+            let f = opts.sort_fn()?;
+            render_to(
+                &img1,
+                &img2,
+                mask.as_ref(),
+                DrawType::Unsort,
+                f.as_ref(),
+                &opts,
+                &output,
+            )
+        }
+        Command::Map {
+            image,
+            output,
+            opts,
+        } => {
+            let img = open(&image)?;
+            let mask = opts
+                .mask()?
+                .map(|m| resize_mask(&m, img.width(), img.height()));
+            let px_map = pixel_map(
+                &img,
+                opts.by,
+                opts.sort_fn()?.as_ref(),
+                opts.row_order,
+                opts.col_order,
+                opts.span(),
+                mask.as_ref(),
+            );
+            map_image(&px_map)?
+                .save(&output)
+                .map_err(|e| format!("{}: {e}", output.display()))
+        }
+        Command::Batch {
+            input,
+            out_dir,
+            name,
+            unsort,
+            opts,
+        } => {
+            let inputs = collect_inputs(&input)?;
+            if inputs.is_empty() {
+                return Err(format!("{input}: no images found"));
+            }
+            std::fs::create_dir_all(&out_dir).map_err(|e| format!("{}: {e}", out_dir.display()))?;
+            let palette = unsort.as_deref().map(open).transpose()?;
+            let mask = opts.mask()?;
+            let f = opts.sort_fn()?;
+            let name = name.unwrap_or_else(|| match unsort {
+                Some(_) => "{stem}_unsorted.png".to_string(),
+                None => "{stem}_sorted.png".to_string(),
+            });
+            let results = batch(&inputs, &out_dir, &name, |img| match &palette {
+                Some(p) => render(img, p, mask.as_ref(), DrawType::Unsort, f.as_ref(), &opts),
+                None => render(img, img, mask.as_ref(), DrawType::Sort, f.as_ref(), &opts),
+            })?;
+            let mut failed = 0;
+            for r in results {
+                match r.output {
+                    Ok(path) => println!("{} -> {}", r.input.display(), path.display()),
+                    Err(e) => {
+                        eprintln!("{}: {e}", r.input.display());
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{failed} of {} images failed", inputs.len()));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("pixel-unsort: {e}");
+        std::process::exit(1);
+    }
+}
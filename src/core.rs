@@ -1,24 +1,300 @@
 use crate::matrix::*;
 use std::ops::Neg;
+use std::str::FromStr;
 
 #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone, Copy)]
-pub(crate) enum SortBy {
+pub enum SortBy {
     Row,
     Column,
     ColRow,
     RowCol,
+    // Parallel lines at an angle in degrees clockwise from the rows.
+    Angle(f32),
+    // Rays out from a centre given in normalized coordinates.
+    Radial { cx: f32, cy: f32 },
+    // Concentric rings around a centre given in normalized coordinates.
+    Rings { cx: f32, cy: f32 },
+    // The whole image as one line along a Hilbert curve.
+    Hilbert,
+    // The whole image as one line along a Morton (Z-order) curve.
+    ZOrder,
+    // Every pixel of the image ranked together. Unsorting always ranks the
+    // unsort image too, so each pixel takes the unsort pixel of the same rank,
+    // spans and the mask are ignored.
+    Global,
+    // Streamlines of a vector field.
+    Flow(FlowField),
+    // The whole image as one clockwise spiral, from the outside in or from
+    // the centre out.
+    Spiral { inward: bool },
     Nothing,
 }
 
 #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone, Copy)]
-pub(crate) enum SortKey {
+pub enum SortKey {
     Lightness,
     Hue,
     Saturation,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    // The largest of the red, green and blue channels.
+    MaxChannel,
+    // The smallest of the red, green and blue channels.
+    MinChannel,
+    // Perceptual lightness, chroma and hue from OKLab / OKLCh.
+    OkLightness,
+    OkChroma,
+    OkHue,
+    // CIELAB L*, a* (green to red) and b* (blue to yellow).
+    LabL,
+    LabA,
+    LabB,
+    // Perceptual distance (delta E) from a reference colour.
+    Distance { color: [u8; 3], space: ColorSpace },
+    // Hue measured from an origin instead of from red.
+    HueFrom(HueOrigin),
 }
 
-// Used to store the location of each pixel in the sort image.
-pub type ImgGrid = Matrix<(usize, usize)>;
+// Where hue keys start on the colour wheel.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone, Copy)]
+pub enum HueOrigin {
+    // An angle in degrees, 0 is red.
+    Degrees(f32),
+    // The largest gap in the hues of each line.
+    Auto,
+}
+
+// Several sort keys combined into one.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone)]
+pub enum Composite {
+    // Sort by the first key and break ties with the next, at most three keys.
+    TieBreak(Vec<SortKey>),
+    // A weighted sum of keys.
+    Blend(Vec<(SortKey, f32)>),
+}
+
+// The colour space distances are measured in.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone, Copy)]
+pub enum ColorSpace {
+    OkLab,
+    Lab,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "row" => Ok(SortBy::Row),
+            "column" | "col" => Ok(SortBy::Column),
+            "colrow" => Ok(SortBy::ColRow),
+            "rowcol" => Ok(SortBy::RowCol),
+            "nothing" => Ok(SortBy::Nothing),
+            "diagonal" => Ok(SortBy::Angle(45.0)),
+            "radial" => Ok(SortBy::Radial { cx: 0.5, cy: 0.5 }),
+            "rings" => Ok(SortBy::Rings { cx: 0.5, cy: 0.5 }),
+            "hilbert" => Ok(SortBy::Hilbert),
+            "zorder" | "morton" => Ok(SortBy::ZOrder),
+            "global" => Ok(SortBy::Global),
+            "spiral" | "spiral:in" => Ok(SortBy::Spiral { inward: true }),
+            "spiral:out" => Ok(SortBy::Spiral { inward: false }),
+            "flow" => Ok(SortBy::Flow(FlowField::Noise {
+                seed: 0,
+                scale: 0.01,
+            })),
+            other => {
+                let err = || format!("unknown sort direction: {s}");
+                let (name, args) = other.split_once(':').ok_or_else(err)?;
+                let centre = || -> Result<(f32, f32), String> {
+                    let (cx, cy) = args.split_once(',').ok_or_else(err)?;
+                    Ok((
+                        cx.trim().parse().map_err(|_| err())?,
+                        cy.trim().parse().map_err(|_| err())?,
+                    ))
+                };
+                match name {
+                    "angle" => Ok(SortBy::Angle(args.parse().map_err(|_| err())?)),
+                    "flow" if args == "gradient" => Ok(SortBy::Flow(FlowField::Gradient)),
+                    "flow" => {
+                        let (seed, scale) = args.split_once(',').ok_or_else(err)?;
+                        Ok(SortBy::Flow(FlowField::Noise {
+                            seed: seed.trim().parse().map_err(|_| err())?,
+                            scale: scale.trim().parse().map_err(|_| err())?,
+                        }))
+                    }
+                    "radial" => centre().map(|(cx, cy)| SortBy::Radial { cx, cy }),
+                    "rings" => centre().map(|(cx, cy)| SortBy::Rings { cx, cy }),
+                    _ => Err(err()),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lightness" => Ok(SortKey::Lightness),
+            "hue" => Ok(SortKey::Hue),
+            "saturation" => Ok(SortKey::Saturation),
+            "red" => Ok(SortKey::Red),
+            "green" => Ok(SortKey::Green),
+            "blue" => Ok(SortKey::Blue),
+            "alpha" => Ok(SortKey::Alpha),
+            "max" | "maxchannel" => Ok(SortKey::MaxChannel),
+            "min" | "minchannel" => Ok(SortKey::MinChannel),
+            "oklightness" | "okl" => Ok(SortKey::OkLightness),
+            "okchroma" => Ok(SortKey::OkChroma),
+            "okhue" => Ok(SortKey::OkHue),
+            "labl" => Ok(SortKey::LabL),
+            "laba" => Ok(SortKey::LabA),
+            "labb" => Ok(SortKey::LabB),
+            "distance" | "okdistance" => Ok(SortKey::Distance {
+                color: [0, 0, 0],
+                space: ColorSpace::OkLab,
+            }),
+            "labdistance" => Ok(SortKey::Distance {
+                color: [0, 0, 0],
+                space: ColorSpace::Lab,
+            }),
+            "hue:auto" => Ok(SortKey::HueFrom(HueOrigin::Auto)),
+            other => match other.strip_prefix("hue:").map(str::parse) {
+                Some(Ok(degrees)) => Ok(SortKey::HueFrom(HueOrigin::Degrees(degrees))),
+                _ => Err(format!("unknown sort key: {s}")),
+            },
+        }
+    }
+}
+
+// The vector field whose streamlines a flow sort follows.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone, Copy)]
+pub enum FlowField {
+    // Perlin noise angles, scale is the noise frequency per pixel.
+    Noise { seed: u64, scale: f32 },
+    // The direction of the luma gradient of the image.
+    Gradient,
+}
+
+// How each row or column is split into spans that are sorted independently,
+// pixels outside of every span stay in place.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone, Copy)]
+pub enum SpanMode {
+    // The whole row or column is one span.
+    Full,
+    // Runs of pixels whose key lies between lower and upper inclusive.
+    Threshold {
+        lower: i16,
+        upper: i16,
+    },
+    // Runs of pixels between edges, a pixel is an edge if the Sobel gradient
+    // magnitude of the luma is at least threshold.
+    Edge {
+        threshold: u16,
+    },
+    // Random length spans between min and max pixels long, each span is left
+    // unsorted with probability skip. The seed makes the spans reproducible.
+    Random {
+        min: usize,
+        max: usize,
+        skip: f32,
+        seed: u64,
+    },
+}
+
+// Used to store the location of each pixel in the sort image, as u32 to keep
+// large maps small.
+pub type ImgGrid = Matrix<(u32, u32)>;
+
+// The location in the sort image of each pixel as the linear index
+// `y * width + x`, a quarter of the size of an ImgGrid of usize pairs.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PixelMap {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u32>,
+}
+
+impl PixelMap {
+    // The map that leaves every pixel in place.
+    pub fn identity(width: usize, height: usize) -> Self {
+        assert!(
+            width * height <= u32::MAX as usize + 1,
+            "{width}x{height} is too large for a pixel map"
+        );
+        PixelMap {
+            width,
+            height,
+            data: (0..(width * height) as u64).map(|i| i as u32).collect(),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> (usize, usize) {
+        let i = self.data[y * self.width + x] as usize;
+        (i % self.width, i / self.width)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, (x1, y1): (usize, usize)) {
+        self.data[y * self.width + x] = (y1 * self.width + x1) as u32;
+    }
+
+    // Check that the map sends exactly one pixel to every location.
+    pub fn check(&self) -> Result<(), String> {
+        if self.data.len() != self.width * self.height {
+            return Err(format!(
+                "a {}x{} pixel map needs {} entries",
+                self.width,
+                self.height,
+                self.width * self.height
+            ));
+        }
+        let mut seen = vec![false; self.data.len()];
+        for &i in &self.data {
+            match seen.get_mut(i as usize) {
+                Some(s) if !*s => *s = true,
+                Some(_) => return Err(format!("{i} is the target of more than one pixel")),
+                None => return Err(format!("{i} is outside the pixel map")),
+            }
+        }
+        Ok(())
+    }
+
+    // The map that sends every pixel back to where it came from.
+    pub fn inverse(&self) -> Self {
+        let mut data = vec![0; self.data.len()];
+        for (i, &j) in self.data.iter().enumerate() {
+            data[j as usize] = i as u32;
+        }
+        PixelMap {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+}
+
+impl From<&ImgGrid> for PixelMap {
+    fn from(grid: &ImgGrid) -> Self {
+        let mut px_map = PixelMap::identity(grid.width, grid.height);
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                let (x1, y1) = grid[y][x];
+                px_map.set(x, y, (x1 as usize, y1 as usize));
+            }
+        }
+        px_map
+    }
+}
+
+impl From<&PixelMap> for ImgGrid {
+    fn from(px_map: &PixelMap) -> Self {
+        Matrix::generate(px_map.width, px_map.height, |x, y| {
+            let (x1, y1) = px_map.get(x, y);
+            (x1 as u32, y1 as u32)
+        })
+    }
+}
 
 // Sort by increasing or decreasing direction of the sort function.
 #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, Clone, Copy)]
@@ -36,6 +312,17 @@ impl SortOrder {
     }
 }
 
+impl FromStr for SortOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ascending" | "asc" => Ok(SortOrder::Ascending),
+            "descending" | "desc" => Ok(SortOrder::Descending),
+            _ => Err(format!("unknown sort order: {s}")),
+        }
+    }
+}
+
 // Change the sort order with the unary negation operator.
 impl Neg for SortOrder {
     type Output = Self;
@@ -46,3 +333,26 @@ impl Neg for SortOrder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_map_test() {
+        let mut px_map = PixelMap::identity(3, 2);
+        assert_eq!(px_map.get(2, 1), (2, 1));
+        px_map.set(2, 1, (0, 1));
+        px_map.set(0, 1, (2, 1));
+        assert_eq!(px_map.data, vec![0, 1, 2, 5, 4, 3]);
+        let grid = ImgGrid::from(&px_map);
+        assert_eq!(grid[1][0], (2, 1));
+        assert_eq!(PixelMap::from(&grid), px_map);
+        assert_eq!(px_map.check(), Ok(()));
+        assert_eq!(px_map.inverse().inverse(), px_map);
+        px_map.set(0, 0, (1, 0));
+        assert!(px_map.check().is_err());
+        px_map.data[0] = 6;
+        assert!(px_map.check().is_err());
+    }
+}
@@ -4,6 +4,9 @@
 mod app;
 pub use app::PixelUnsortApp;
 pub mod art;
+pub mod batch;
 pub mod core;
 pub mod matrix;
+pub mod paths;
 pub mod sortfns;
+pub mod spans;
@@ -197,6 +197,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn put_test() {
         let mut m = Matrix::generate(2, 3, |i, j| (i, j));
         assert_eq!(m.put(1, 1, (5, 5)), true);
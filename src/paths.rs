@@ -0,0 +1,598 @@
+//! Generate the lines of pixels that get sorted. The lines of an image are
+//! disjoint and together cover every pixel exactly once.
+
+use crate::matrix::Matrix;
+use crate::sortfns::gradient;
+use image::DynamicImage;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// A sequence of pixel locations in the order they are sorted.
+pub type Line = Vec<(usize, usize)>;
+
+/// Check that every pixel of a set of lines is inside the image and on at
+/// most one line.
+pub fn check_lines(width: usize, height: usize, lines: &[Line]) -> Result<(), String> {
+    let mut seen = Matrix::fill(width, height, false);
+    for (x, y) in lines.iter().flatten() {
+        if *x >= width || *y >= height {
+            return Err(format!("({x}, {y}) is outside the {width}x{height} image"));
+        }
+        if seen[*y][*x] {
+            return Err(format!("({x}, {y}) is on more than one line"));
+        }
+        seen[*y][*x] = true;
+    }
+    Ok(())
+}
+
+/// The rows of the image, each from left to right.
+pub fn row_lines(width: usize, height: usize) -> Vec<Line> {
+    (0..height)
+        .map(|y| (0..width).map(|x| (x, y)).collect())
+        .collect()
+}
+
+/// The columns of the image, each from top to bottom.
+pub fn column_lines(width: usize, height: usize) -> Vec<Line> {
+    (0..width)
+        .map(|x| (0..height).map(|y| (x, y)).collect())
+        .collect()
+}
+
+/// Parallel lines at an angle in degrees, measured clockwise from the
+/// positive x axis, so 0 is left to right along the rows and 90 is top to
+/// bottom along the columns. Each line has exactly one pixel in every column
+/// (or every row for steep angles) it crosses, so no pixel is skipped or
+/// repeated the way rotating and cropping the image would.
+pub fn angle_lines(width: usize, height: usize, degrees: f32) -> Vec<Line> {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let steep = sin.abs() > cos.abs();
+    // Lines are indexed by where they cross the first column (or row), walk
+    // the other axis and shift each step by the slope.
+    let (outer, inner, slope) = if steep {
+        (height, width, cos / sin)
+    } else {
+        (width, height, sin / cos)
+    };
+    let offsets = (0..outer)
+        .map(|i| -(i as f32 * slope).round() as isize)
+        .collect::<Vec<_>>();
+    let min = *offsets.iter().min().unwrap_or(&0);
+    let max = *offsets.iter().max().unwrap_or(&0);
+    let mut lines = vec![Vec::new(); (inner as isize + max - min) as usize];
+    for (i, offset) in offsets.iter().enumerate() {
+        for j in 0..inner {
+            let id = (j as isize + offset - min) as usize;
+            lines[id].push(if steep { (j, i) } else { (i, j) });
+        }
+    }
+    let reverse = if steep { sin < 0.0 } else { cos < 0.0 };
+    lines.retain(|l| !l.is_empty());
+    if reverse {
+        lines.iter_mut().for_each(|l| l.reverse());
+    }
+    lines
+}
+
+// Group the pixels into lines by `bin` and order each line by `position`.
+fn binned_lines<B, P>(width: usize, height: usize, bins: usize, bin: B, position: P) -> Vec<Line>
+where
+    B: Fn(usize, usize) -> usize,
+    P: Fn(usize, usize) -> f32,
+{
+    let mut lines = vec![Vec::new(); bins];
+    for y in 0..height {
+        for x in 0..width {
+            lines[bin(x, y)].push((position(x, y), (x, y)));
+        }
+    }
+    lines
+        .into_iter()
+        .filter(|l| !l.is_empty())
+        .map(|mut l| {
+            l.sort_by(|a, b| a.0.total_cmp(&b.0));
+            l.into_iter().map(|(_, p)| p).collect()
+        })
+        .collect()
+}
+
+// The centre in pixels from normalized coordinates and the distance from it
+// to the farthest corner.
+fn centre(width: usize, height: usize, cx: f32, cy: f32) -> (f32, f32, f32) {
+    let cx = cx * (width.max(1) - 1) as f32;
+    let cy = cy * (height.max(1) - 1) as f32;
+    let far_x = cx.max(width as f32 - 1.0 - cx);
+    let far_y = cy.max(height as f32 - 1.0 - cy);
+    (cx, cy, far_x.hypot(far_y))
+}
+
+/// Rays from the centre `(cx, cy)`, given in normalized coordinates, out to
+/// the border. There are enough rays that they are about one pixel apart at
+/// the farthest corner, every pixel is on the ray closest to its angle.
+pub fn radial_lines(width: usize, height: usize, cx: f32, cy: f32) -> Vec<Line> {
+    use std::f32::consts::TAU;
+    let (cx, cy, radius) = centre(width, height, cx, cy);
+    let rays = ((TAU * radius).ceil() as usize).max(1);
+    binned_lines(
+        width,
+        height,
+        rays,
+        |x, y| {
+            let angle = (y as f32 - cy).atan2(x as f32 - cx).rem_euclid(TAU);
+            (angle / TAU * rays as f32) as usize % rays
+        },
+        |x, y| (x as f32 - cx).hypot(y as f32 - cy),
+    )
+}
+
+/// Concentric rings one pixel wide around the centre `(cx, cy)`, given in
+/// normalized coordinates. Each ring runs clockwise starting from the right.
+pub fn ring_lines(width: usize, height: usize, cx: f32, cy: f32) -> Vec<Line> {
+    use std::f32::consts::TAU;
+    let (cx, cy, radius) = centre(width, height, cx, cy);
+    binned_lines(
+        width,
+        height,
+        radius.round() as usize + 1,
+        |x, y| (x as f32 - cx).hypot(y as f32 - cy).round() as usize,
+        |x, y| (y as f32 - cy).atan2(x as f32 - cx).rem_euclid(TAU),
+    )
+}
+
+/// A single line through the whole image in row major order.
+pub fn global_lines(width: usize, height: usize) -> Vec<Line> {
+    vec![(0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect()]
+}
+
+/// A single clockwise spiral through the whole image, from the top left
+/// corner inward or, if `inward` is false, from the centre outward.
+pub fn spiral_lines(width: usize, height: usize, inward: bool) -> Vec<Line> {
+    let mut line = Vec::with_capacity(width * height);
+    let (mut left, mut top, mut right, mut bottom) = (0, 0, width, height);
+    while left < right && top < bottom {
+        line.extend((left..right).map(|x| (x, top)));
+        line.extend((top + 1..bottom).map(|y| (right - 1, y)));
+        if top + 1 < bottom {
+            line.extend((left..right - 1).rev().map(|x| (x, bottom - 1)));
+        }
+        if left + 1 < right {
+            line.extend((top + 1..bottom - 1).rev().map(|y| (left, y)));
+        }
+        (left, top, right, bottom) = (left + 1, top + 1, right - 1, bottom - 1);
+    }
+    if !inward {
+        line.reverse();
+    }
+    vec![line]
+}
+
+// Order every pixel by its index along a space filling curve on the smallest
+// power of two square that holds the image. Pixels of the square outside of
+// the image are skipped, so any image size works.
+fn curve_lines<F>(width: usize, height: usize, index: F) -> Vec<Line>
+where
+    F: Fn(usize, usize, usize) -> usize,
+{
+    let n = width.max(height).next_power_of_two();
+    let mut line = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            line.push((x, y));
+        }
+    }
+    line.sort_by_cached_key(|(x, y)| index(n, *x, *y));
+    vec![line]
+}
+
+/// A single line through the whole image along a Hilbert curve.
+pub fn hilbert_lines(width: usize, height: usize) -> Vec<Line> {
+    curve_lines(width, height, hilbert_index)
+}
+
+/// A single line through the whole image along a Morton (Z-order) curve.
+pub fn z_order_lines(width: usize, height: usize) -> Vec<Line> {
+    curve_lines(width, height, |_, x, y| z_order_index(x, y))
+}
+
+// Distance of (x, y) along the Hilbert curve filling an n by n square, n a
+// power of two.
+fn hilbert_index(n: usize, x: usize, y: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve inside it has the base orientation.
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+// Interleave the bits of x and y, x in the even bits.
+fn z_order_index(x: usize, y: usize) -> usize {
+    let spread = |v: usize| {
+        let mut v = v as u64 & 0xffff_ffff;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+        v as usize
+    };
+    spread(x) | (spread(y) << 1)
+}
+
+/// Streamlines of the vector field `direction`, which gives the direction to
+/// move from each pixel. Starting from each pixel not yet on a line, in row
+/// major order, a line is traced one pixel at a time both down and up the
+/// field until it leaves the image or no pixel ahead is free. Where the next
+/// pixel is already on a line it steps to a free neighbour within 45 degrees
+/// of the field. The two halves are joined so the line runs along the field.
+pub fn flow_lines<F>(width: usize, height: usize, direction: F) -> Vec<Line>
+where
+    F: Fn(usize, usize) -> (f32, f32),
+{
+    let mut visited = Matrix::fill(width, height, false);
+    let mut lines = Vec::new();
+    for y0 in 0..height {
+        for x0 in 0..width {
+            if visited[y0][x0] {
+                continue;
+            }
+            visited[y0][x0] = true;
+            let mut line = trace(&mut visited, (x0, y0), |x, y| {
+                let (dx, dy) = direction(x, y);
+                (-dx, -dy)
+            });
+            line.reverse();
+            line.push((x0, y0));
+            line.extend(trace(&mut visited, (x0, y0), &direction));
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+// Follow the field from a start pixel, marking each new pixel visited. The
+// start is not included.
+fn trace<F>(visited: &mut Matrix<bool>, start: (usize, usize), direction: F) -> Line
+where
+    F: Fn(usize, usize) -> (f32, f32),
+{
+    let (width, height) = (visited.width, visited.height);
+    let mut line = Vec::new();
+    let (mut px, mut py) = start;
+    let (mut x, mut y) = (px as f32, py as f32);
+    // Small steps can stay on the same pixel, give up after a few.
+    let mut stuck = 0;
+    while stuck < 4 {
+        let (dx, dy) = direction(px, py);
+        let norm = dx.hypot(dy);
+        if norm == 0.0 {
+            break;
+        }
+        x += dx / norm;
+        y += dy / norm;
+        let (nx, ny) = (x.round(), y.round());
+        if nx < 0.0 || ny < 0.0 || nx >= width as f32 || ny >= height as f32 {
+            break;
+        }
+        let (mut nx, mut ny) = (nx as usize, ny as usize);
+        if (nx, ny) == (px, py) {
+            stuck += 1;
+            continue;
+        }
+        if visited[ny][nx] {
+            // Step to the free neighbour closest to the field instead, as
+            // long as it is within 45 degrees.
+            let Some(free) = free_neighbour(visited, (px, py), (dx / norm, dy / norm)) else {
+                break;
+            };
+            (nx, ny) = free;
+            (x, y) = (nx as f32, ny as f32);
+        }
+        visited[ny][nx] = true;
+        line.push((nx, ny));
+        (px, py) = (nx, ny);
+        stuck = 0;
+    }
+    line
+}
+
+// The neighbour of a pixel not yet on a line that is most in the direction
+// (dx, dy) of unit length, if any is within 45 degrees of it.
+fn free_neighbour(
+    visited: &Matrix<bool>,
+    (px, py): (usize, usize),
+    (dx, dy): (f32, f32),
+) -> Option<(usize, usize)> {
+    let mut best = None;
+    let mut best_cos = std::f32::consts::FRAC_1_SQRT_2 - 1e-4;
+    for (ox, oy) in [
+        (1, 0),
+        (1, 1),
+        (0, 1),
+        (-1, 1),
+        (-1, 0),
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+    ] {
+        let (nx, ny) = (px as isize + ox, py as isize + oy);
+        if nx < 0 || ny < 0 || nx >= visited.width as isize || ny >= visited.height as isize {
+            continue;
+        }
+        let (nx, ny) = (nx as usize, ny as usize);
+        let cos = (ox as f32 * dx + oy as f32 * dy) / (ox as f32).hypot(oy as f32);
+        if !visited[ny][nx] && cos > best_cos {
+            (best, best_cos) = (Some((nx, ny)), cos);
+        }
+    }
+    best
+}
+
+/// Streamlines of a Perlin noise field of angles, `scale` is the noise
+/// frequency per pixel so smaller values give smoother curves.
+pub fn noise_flow_lines(width: usize, height: usize, seed: u64, scale: f32) -> Vec<Line> {
+    use std::f32::consts::TAU;
+    let perlin = Perlin::new(seed);
+    flow_lines(width, height, |x, y| {
+        let angle = perlin.get(x as f32 * scale, y as f32 * scale) * TAU;
+        (angle.cos(), angle.sin())
+    })
+}
+
+/// Streamlines of the luma gradient of the image, flat areas flow to the
+/// right.
+pub fn gradient_flow_lines(img: &DynamicImage) -> Vec<Line> {
+    let grad = gradient(img);
+    flow_lines(grad.width, grad.height, |x, y| match grad[y][x] {
+        (0, 0) => (1.0, 0.0),
+        (gx, gy) => (gx as f32, gy as f32),
+    })
+}
+
+// Classic 2d Perlin gradient noise with a seeded permutation table.
+struct Perlin {
+    perm: Vec<u8>,
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Self {
+        let mut perm = (0..=255).collect::<Vec<u8>>();
+        perm.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        perm.extend_from_within(..);
+        Self { perm }
+    }
+
+    // Noise in about -1..1 at (x, y).
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+        let grad = |hash: u8, x: f32, y: f32| match hash & 7 {
+            0 => x + y,
+            1 => x - y,
+            2 => -x + y,
+            3 => -x - y,
+            4 => x,
+            5 => -x,
+            6 => y,
+            _ => -y,
+        };
+        let (xf, yf) = (x.floor(), y.floor());
+        let (xi, yi) = ((xf as i64 & 255) as usize, (yf as i64 & 255) as usize);
+        let (x, y) = (x - xf, y - yf);
+        let (u, v) = (fade(x), fade(y));
+        let p = &self.perm;
+        let (a, b) = (p[xi] as usize + yi, p[xi + 1] as usize + yi);
+        lerp(
+            v,
+            lerp(u, grad(p[a], x, y), grad(p[b], x - 1.0, y)),
+            lerp(
+                u,
+                grad(p[a + 1], x, y - 1.0),
+                grad(p[b + 1], x - 1.0, y - 1.0),
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers(width: usize, height: usize, lines: &[Line]) -> bool {
+        check_lines(width, height, lines).is_ok()
+            && lines.iter().map(|l| l.len()).sum::<usize>() == width * height
+    }
+
+    #[test]
+    fn check_lines_test() {
+        assert!(check_lines(2, 2, &[vec![(0, 0), (1, 1)], vec![(1, 0)]]).is_ok());
+        assert!(check_lines(2, 2, &[vec![(0, 0), (2, 1)]]).is_err());
+        assert!(check_lines(2, 2, &[vec![(0, 0)], vec![(0, 0)]]).is_err());
+    }
+
+    #[test]
+    fn rows_columns_test() {
+        assert!(covers(5, 3, &row_lines(5, 3)));
+        assert!(covers(5, 3, &column_lines(5, 3)));
+        assert_eq!(row_lines(5, 3), angle_lines(5, 3, 0.0));
+        assert_eq!(column_lines(5, 3), angle_lines(5, 3, 90.0));
+    }
+
+    #[test]
+    fn angle_covers_test() {
+        for degrees in [0.0, 17.0, 30.0, 45.0, 60.0, 90.0, 135.0, 200.0, 300.0] {
+            assert!(covers(13, 7, &angle_lines(13, 7, degrees)));
+        }
+    }
+
+    #[test]
+    fn angle_rows_test() {
+        let lines = angle_lines(3, 2, 0.0);
+        assert_eq!(
+            lines,
+            vec![vec![(0, 0), (1, 0), (2, 0)], vec![(0, 1), (1, 1), (2, 1)]]
+        );
+        let lines = angle_lines(2, 3, 90.0);
+        assert_eq!(
+            lines,
+            vec![vec![(0, 0), (0, 1), (0, 2)], vec![(1, 0), (1, 1), (1, 2)]]
+        );
+        let lines = angle_lines(3, 1, 180.0);
+        assert_eq!(lines, vec![vec![(2, 0), (1, 0), (0, 0)]]);
+    }
+
+    #[test]
+    fn polar_covers_test() {
+        for (cx, cy) in [(0.5, 0.5), (0.0, 0.0), (1.0, 0.3)] {
+            assert!(covers(13, 7, &radial_lines(13, 7, cx, cy)));
+            assert!(covers(13, 7, &ring_lines(13, 7, cx, cy)));
+        }
+        assert!(covers(1, 1, &radial_lines(1, 1, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn rings_test() {
+        for line in ring_lines(11, 11, 0.5, 0.5) {
+            let r = |(x, y): (usize, usize)| (x as f32 - 5.0).hypot(y as f32 - 5.0).round();
+            assert!(line.iter().all(|p| r(*p) == r(line[0])));
+        }
+    }
+
+    #[test]
+    fn radial_test() {
+        for line in radial_lines(11, 11, 0.5, 0.5) {
+            let r = |(x, y): (usize, usize)| (x as f32 - 5.0).hypot(y as f32 - 5.0);
+            for (a, b) in line.iter().zip(line.iter().skip(1)) {
+                assert!(r(*a) <= r(*b));
+            }
+        }
+    }
+
+    #[test]
+    fn curve_covers_test() {
+        for (w, h) in [(8, 8), (13, 7), (1, 5)] {
+            assert!(covers(w, h, &hilbert_lines(w, h)));
+            assert!(covers(w, h, &z_order_lines(w, h)));
+            assert!(covers(w, h, &global_lines(w, h)));
+            assert!(covers(w, h, &spiral_lines(w, h, true)));
+        }
+    }
+
+    #[test]
+    fn hilbert_test() {
+        // On a full power of two square consecutive points are neighbours.
+        let line = &hilbert_lines(16, 16)[0];
+        assert_eq!(line[0], (0, 0));
+        for ((x0, y0), (x1, y1)) in line.iter().zip(line.iter().skip(1)) {
+            assert_eq!(x0.abs_diff(*x1) + y0.abs_diff(*y1), 1);
+        }
+    }
+
+    #[test]
+    fn spiral_test() {
+        let line = &spiral_lines(3, 3, true)[0];
+        assert_eq!(
+            line,
+            &vec![
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (2, 1),
+                (2, 2),
+                (1, 2),
+                (0, 2),
+                (0, 1),
+                (1, 1)
+            ]
+        );
+        let line = &spiral_lines(3, 3, false)[0];
+        assert_eq!(line[0], (1, 1));
+        for (w, h) in [(1, 4), (4, 1), (6, 5)] {
+            let line = &spiral_lines(w, h, true)[0];
+            for ((x0, y0), (x1, y1)) in line.iter().zip(line.iter().skip(1)) {
+                assert_eq!(x0.abs_diff(*x1) + y0.abs_diff(*y1), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn z_order_test() {
+        let line = &z_order_lines(4, 2)[0];
+        assert_eq!(
+            line,
+            &vec![
+                (0, 0),
+                (1, 0),
+                (0, 1),
+                (1, 1),
+                (2, 0),
+                (3, 0),
+                (2, 1),
+                (3, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn flow_covers_test() {
+        for scale in [0.01, 0.1, 1.0] {
+            let lines = noise_flow_lines(23, 17, 5, scale);
+            assert!(covers(23, 17, &lines));
+            for line in lines {
+                for ((x0, y0), (x1, y1)) in line.iter().zip(line.iter().skip(1)) {
+                    assert!(x0.abs_diff(*x1) <= 1 && y0.abs_diff(*y1) <= 1);
+                }
+            }
+        }
+        let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(9, 6, |x, y| {
+            image::Rgba([(x * 20) as u8, (y * 30) as u8, 0, 255])
+        }));
+        assert!(covers(9, 6, &gradient_flow_lines(&img)));
+    }
+
+    #[test]
+    fn flow_length_test() {
+        // Lines run the length of the field, not just until they meet an
+        // earlier line. A field pointing left gives the rows.
+        let lines = flow_lines(40, 30, |_, _| (-1.0, 0.0));
+        assert_eq!(lines.len(), 30);
+        assert!(lines.iter().all(|line| line.len() == 40));
+        // Most pixels of a smooth noise field are on long lines.
+        let lines = noise_flow_lines(200, 150, 5, 0.01);
+        let mut lengths = lines
+            .iter()
+            .flat_map(|line| vec![line.len(); line.len()])
+            .collect::<Vec<_>>();
+        lengths.sort();
+        assert!(lengths[lengths.len() / 2] >= 30);
+        let short = lengths.iter().filter(|&&n| n <= 2).count();
+        assert!(short * 10 < lengths.len());
+    }
+
+    #[test]
+    fn angle_diagonal_test() {
+        for line in angle_lines(5, 4, 45.0) {
+            for ((x0, y0), (x1, y1)) in line.iter().zip(line.iter().skip(1)) {
+                assert_eq!((x1 - x0, y1 - y0), (1, 1));
+            }
+        }
+    }
+}
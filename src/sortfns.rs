@@ -1,21 +1,464 @@
+use crate::core::{ColorSpace, Composite, HueOrigin, SortKey};
+use crate::matrix::Matrix;
 use image::*;
+use rayon::prelude::*;
+use std::cmp::Ordering;
 
-pub(crate) type SortFn = fn(Rgba<u8>) -> i16;
+/// Turns a pixel into the key it is sorted by. Implemented for every closure
+/// `Fn(Rgba<u8>) -> Key`, so a key can capture parameters such as a reference
+/// colour. Lines are sorted in parallel, hence `Send + Sync`.
+pub trait SortKeyFn: Send + Sync {
+    fn key(&self, c: Rgba<u8>) -> Key;
 
-pub(crate) fn luma(c: Rgba<u8>) -> i16 {
-    c.to_luma()[0] as i16
+    /// Adjust the keys of a whole line once they are computed, for keys that
+    /// depend on the other pixels of the line. Keys are left alone by default.
+    fn adjust_line(&self, _keys: &mut [Key]) {}
+
+    /// Whether keys depend on the other pixels of the line in a way that
+    /// `adjust_line` can not fix up, so they can only be found from the whole
+    /// line of pixels with `line_keys`. False by default.
+    fn by_line(&self) -> bool {
+        false
+    }
+
+    /// The keys of a whole line of pixels. By default the key of each pixel,
+    /// then adjusted as a line.
+    fn line_keys(&self, pixels: &[Rgba<u8>]) -> Vec<Key> {
+        let mut keys = pixels.iter().map(|c| self.key(*c)).collect::<Vec<_>>();
+        self.adjust_line(&mut keys);
+        keys
+    }
+}
+
+impl<F> SortKeyFn for F
+where
+    F: Fn(Rgba<u8>) -> Key + Send + Sync,
+{
+    fn key(&self, c: Rgba<u8>) -> Key {
+        self(c)
+    }
+}
+
+/// The sort key of every pixel of an image, computed once up front. It keeps
+/// the key function to adjust the keys of each line. For keys found a line
+/// at a time it keeps the pixels instead.
+pub struct KeyBuffer<'a, F: SortKeyFn + ?Sized> {
+    stored: Stored,
+    f: &'a F,
+}
+
+enum Stored {
+    Keys(Matrix<Key>),
+    Pixels(RgbaImage),
+}
+
+impl<'a, F: SortKeyFn + ?Sized> KeyBuffer<'a, F> {
+    pub fn new(img: &DynamicImage, f: &'a F) -> Self {
+        let buffer = img.to_rgba8();
+        if f.by_line() {
+            return KeyBuffer {
+                stored: Stored::Pixels(buffer),
+                f,
+            };
+        }
+        let data = buffer
+            .as_raw()
+            .par_chunks_exact(4)
+            .map(|p| f.key(Rgba([p[0], p[1], p[2], p[3]])))
+            .collect();
+        KeyBuffer {
+            stored: Stored::Keys(Matrix::new(buffer.width(), buffer.height(), data)),
+            f,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match &self.stored {
+            Stored::Keys(keys) => keys.width,
+            Stored::Pixels(pixels) => pixels.width() as usize,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match &self.stored {
+            Stored::Keys(keys) => keys.height,
+            Stored::Pixels(pixels) => pixels.height() as usize,
+        }
+    }
+
+    /// The keys of the pixels at `coords`, adjusted as one line.
+    pub fn line(&self, coords: &[(usize, usize)]) -> Vec<Key> {
+        match &self.stored {
+            Stored::Keys(keys) => {
+                let mut line = coords.iter().map(|(x, y)| keys[*y][*x]).collect::<Vec<_>>();
+                self.f.adjust_line(&mut line);
+                line
+            }
+            Stored::Pixels(pixels) => {
+                let line = coords
+                    .iter()
+                    .map(|(x, y)| *pixels.get_pixel(*x as u32, *y as u32))
+                    .collect::<Vec<_>>();
+                self.f.line_keys(&line)
+            }
+        }
+    }
+}
+
+/// The value a pixel is sorted by. Keys compare with `f64::total_cmp` so
+/// every value, even NaN, has a place in the order and sorts stay stable.
+/// Any `f32` or `u32` converts to a key exactly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Key(pub f64);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl From<f32> for Key {
+    fn from(k: f32) -> Self {
+        Key(k as f64)
+    }
+}
+
+impl From<u32> for Key {
+    fn from(k: u32) -> Self {
+        Key(k as f64)
+    }
+}
+
+impl From<u8> for Key {
+    fn from(k: u8) -> Self {
+        Key(k as f64)
+    }
+}
+
+impl From<i16> for Key {
+    fn from(k: i16) -> Self {
+        Key(k as f64)
+    }
+}
+
+// The sort function used for a given sort key.
+pub fn key_fn(key: SortKey) -> Box<dyn SortKeyFn> {
+    let f: fn(Rgba<u8>) -> Key = match key {
+        SortKey::Lightness => luma,
+        SortKey::Hue => hue,
+        SortKey::Saturation => sat,
+        SortKey::Red => red,
+        SortKey::Green => green,
+        SortKey::Blue => blue,
+        SortKey::Alpha => alpha,
+        SortKey::MaxChannel => max_channel,
+        SortKey::MinChannel => min_channel,
+        SortKey::OkLightness => ok_lightness,
+        SortKey::OkChroma => ok_chroma,
+        SortKey::OkHue => ok_hue,
+        SortKey::LabL => lab_l,
+        SortKey::LabA => lab_a,
+        SortKey::LabB => lab_b,
+        SortKey::Distance { color, space } => return Box::new(distance(color, space)),
+        SortKey::HueFrom(HueOrigin::Degrees(origin)) => {
+            let origin = origin as f64 / 360.0 * 255.0;
+            return Box::new(move |c| Key((hue(c).0 - origin).rem_euclid(255.0)));
+        }
+        SortKey::HueFrom(HueOrigin::Auto) => return Box::new(AutoHue),
+    };
+    Box::new(f)
+}
+
+// The sort function for a composite of keys.
+pub fn composite_fn(composite: &Composite) -> Box<dyn SortKeyFn> {
+    let (keys, tie_break) = match composite {
+        Composite::TieBreak(keys) => (keys.iter().take(3).map(|k| (*k, 1.0)).collect(), true),
+        Composite::Blend(keys) => (keys.clone(), false),
+    };
+    Box::new(CompositeKey {
+        fs: keys
+            .into_iter()
+            .map(|(k, w)| (key_fn(k), w as f64))
+            .collect(),
+        tie_break,
+    })
+}
+
+// Keys combined by tie breaking or by a weighted sum. If any key depends on
+// the whole line, the keys of a line are found for each key and combined.
+struct CompositeKey {
+    fs: Vec<(Box<dyn SortKeyFn>, f64)>,
+    tie_break: bool,
+}
+
+impl CompositeKey {
+    fn combine(&self, keys: impl Iterator<Item = Key>) -> Key {
+        if self.tie_break {
+            tie_break(keys)
+        } else {
+            Key(keys.zip(&self.fs).map(|(k, (_, w))| w * k.0).sum())
+        }
+    }
+}
+
+impl SortKeyFn for CompositeKey {
+    fn key(&self, c: Rgba<u8>) -> Key {
+        self.combine(self.fs.iter().map(|(f, _)| f.key(c)))
+    }
+
+    fn by_line(&self) -> bool {
+        self.fs.iter().any(|(f, _)| f.by_line())
+    }
+
+    fn line_keys(&self, pixels: &[Rgba<u8>]) -> Vec<Key> {
+        let lines = self
+            .fs
+            .iter()
+            .map(|(f, _)| f.line_keys(pixels))
+            .collect::<Vec<_>>();
+        (0..pixels.len())
+            .map(|i| self.combine(lines.iter().map(|line| line[i])))
+            .collect()
+    }
+}
+
+// Pack up to three keys into one that orders by the first key, then the
+// second, then the third. Each key is clamped to 0..1024 and kept to 1/64,
+// 16 bits apiece, so keys closer than that count as a tie.
+fn tie_break(keys: impl Iterator<Item = Key>) -> Key {
+    let bits = |k: Key| (k.0.clamp(0.0, 1024.0 - 1.0 / 64.0) * 64.0).floor();
+    Key(keys.fold(0.0, |packed, k| packed * 65536.0 + bits(k)))
+}
+
+pub fn luma(c: Rgba<u8>) -> Key {
+    c.to_luma()[0].into()
+}
+
+pub fn red(c: Rgba<u8>) -> Key {
+    c[0].into()
+}
+
+pub fn green(c: Rgba<u8>) -> Key {
+    c[1].into()
+}
+
+pub fn blue(c: Rgba<u8>) -> Key {
+    c[2].into()
 }
 
-pub(crate) fn hue(c: Rgba<u8>) -> i16 {
+pub fn alpha(c: Rgba<u8>) -> Key {
+    c[3].into()
+}
+
+pub fn max_channel(c: Rgba<u8>) -> Key {
+    c[0].max(c[1]).max(c[2]).into()
+}
+
+pub fn min_channel(c: Rgba<u8>) -> Key {
+    c[0].min(c[1]).min(c[2]).into()
+}
+
+// The perceptual keys are scaled to about 0..=255 like the others.
+
+pub fn ok_lightness(c: Rgba<u8>) -> Key {
+    (oklab(c).0 * 255.0).into()
+}
+
+pub fn ok_chroma(c: Rgba<u8>) -> Key {
+    // The most saturated sRGB color, magenta, has an OKLCh chroma of 0.3225.
+    let (_, a, b) = oklab(c);
+    (a.hypot(b) / 0.33 * 255.0).into()
+}
+
+pub fn ok_hue(c: Rgba<u8>) -> Key {
+    let (_, a, b) = oklab(c);
+    let hue = b.atan2(a).to_degrees().rem_euclid(360.0);
+    (hue / 360.0 * 255.0).into()
+}
+
+pub fn lab_l(c: Rgba<u8>) -> Key {
+    (cielab(c).0 / 100.0 * 255.0).into()
+}
+
+pub fn lab_a(c: Rgba<u8>) -> Key {
+    (cielab(c).1 + 128.0).into()
+}
+
+pub fn lab_b(c: Rgba<u8>) -> Key {
+    (cielab(c).2 + 128.0).into()
+}
+
+// Decode a gamma encoded sRGB channel to linear light.
+fn linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// The perceptual distance of a pixel from a reference colour. OKLab distances
+// are scaled by 255 so black to white is 255, like the other keys, CIELAB
+// delta E*ab is used as is.
+pub fn distance(color: [u8; 3], space: ColorSpace) -> impl SortKeyFn {
+    let lab = move |c| match space {
+        ColorSpace::OkLab => {
+            let (l, a, b) = oklab(c);
+            (l * 255.0, a * 255.0, b * 255.0)
+        }
+        ColorSpace::Lab => cielab(c),
+    };
+    let (l0, a0, b0) = lab(Rgba([color[0], color[1], color[2], 255]));
+    move |c: Rgba<u8>| {
+        let (l, a, b) = lab(c);
+        let d = (l - l0).powi(2) + (a - a0).powi(2) + (b - b0).powi(2);
+        Key::from(d.sqrt())
+    }
+}
+
+/// The OKLab (L, a, b) of a color, L from 0 to 1.
+pub fn oklab(c: Rgba<u8>) -> (f32, f32, f32) {
+    let (r, g, b) = (linear(c[0]), linear(c[1]), linear(c[2]));
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    (
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    )
+}
+
+/// The CIELAB (L*, a*, b*) of a color under the D65 white point, L* from 0
+/// to 100.
+pub fn cielab(c: Rgba<u8>) -> (f32, f32, f32) {
+    let (r, g, b) = (linear(c[0]), linear(c[1]), linear(c[2]));
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+    let f = |t: f32| {
+        const DELTA: f32 = 6.0 / 29.0;
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+// The Sobel gradient (gx, gy) of the luma at every pixel. Pixels past the
+// border are clamped to the nearest edge pixel.
+pub fn gradient(img: &DynamicImage) -> Matrix<(i32, i32)> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let buffer = img.to_rgba8();
+    let lum = Matrix::generate(width, height, |x, y| {
+        buffer.get_pixel(x as u32, y as u32).to_luma()[0] as i32
+    });
+    let at = |x: usize, y: usize, dx: isize, dy: isize| {
+        let x1 = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+        let y1 = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+        lum[y1][x1]
+    };
+    let data = (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let gx = at(x, y, 1, -1) + 2 * at(x, y, 1, 0) + at(x, y, 1, 1)
+                - at(x, y, -1, -1)
+                - 2 * at(x, y, -1, 0)
+                - at(x, y, -1, 1);
+            let gy = at(x, y, -1, 1) + 2 * at(x, y, 0, 1) + at(x, y, 1, 1)
+                - at(x, y, -1, -1)
+                - 2 * at(x, y, 0, -1)
+                - at(x, y, 1, -1);
+            (gx, gy)
+        })
+        .collect();
+    Matrix::new(width, height, data)
+}
+
+pub fn hue(c: Rgba<u8>) -> Key {
     let hsl = hsl(c[0], c[1], c[2]);
     let hue = hsl.0 / 360.0 * 255.0;
-    hue as i16
+    hue.into()
 }
 
-pub(crate) fn sat(c: Rgba<u8>) -> i16 {
+// Hue that starts at the largest gap in the hue histogram of each line, so a
+// run of similar hues is not split across both ends.
+struct AutoHue;
+
+impl SortKeyFn for AutoHue {
+    fn key(&self, c: Rgba<u8>) -> Key {
+        hue(c)
+    }
+
+    fn by_line(&self) -> bool {
+        true
+    }
+
+    fn adjust_line(&self, keys: &mut [Key]) {
+        let origin = largest_gap(keys);
+        for k in keys {
+            k.0 = (k.0 - origin).rem_euclid(255.0);
+        }
+    }
+}
+
+// The smallest hue key after the longest run of empty bins in a 256 bin hue
+// histogram, going round the circle. Zero if no bin is empty.
+fn largest_gap(keys: &[Key]) -> f64 {
+    const BINS: usize = 256;
+    let bin = |k: &Key| ((k.0 / 255.0 * BINS as f64) as usize).min(BINS - 1);
+    let mut hist = [0usize; BINS];
+    for k in keys {
+        hist[bin(k)] += 1;
+    }
+    let Some(full) = hist.iter().position(|&n| n > 0) else {
+        return 0.0;
+    };
+    // Walk once round the circle starting at an occupied bin.
+    let (mut best, mut best_end, mut run) = (0, 0, 0);
+    for i in 1..=BINS {
+        let b = (full + i) % BINS;
+        if hist[b] == 0 {
+            run += 1;
+        } else {
+            if run > best {
+                (best, best_end) = (run, b);
+            }
+            run = 0;
+        }
+    }
+    if best == 0 {
+        return 0.0;
+    }
+    keys.iter()
+        .filter(|k| bin(k) == best_end)
+        .map(|k| k.0)
+        .fold(f64::INFINITY, f64::min)
+}
+
+pub fn sat(c: Rgba<u8>) -> Key {
     let hsl = hsl(c[0], c[1], c[2]);
     let sat = hsl.1 * 255.0;
-    sat as i16
+    sat.into()
 }
 
 fn hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
@@ -59,3 +502,159 @@ fn hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
 
     (h_degrees, s, l)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32, f32), b: (f32, f32, f32), eps: f32) -> bool {
+        (a.0 - b.0).abs() < eps && (a.1 - b.1).abs() < eps && (a.2 - b.2).abs() < eps
+    }
+
+    #[test]
+    fn oklab_test() {
+        assert!(close(
+            oklab(Rgba([255, 255, 255, 255])),
+            (1.0, 0.0, 0.0),
+            1e-3
+        ));
+        assert!(close(oklab(Rgba([0, 0, 0, 255])), (0.0, 0.0, 0.0), 1e-3));
+        assert!(close(
+            oklab(Rgba([255, 0, 0, 255])),
+            (0.628, 0.2249, 0.1258),
+            1e-3
+        ));
+    }
+
+    #[test]
+    fn cielab_test() {
+        assert!(close(
+            cielab(Rgba([255, 255, 255, 255])),
+            (100.0, 0.0, 0.0),
+            0.05
+        ));
+        assert!(close(
+            cielab(Rgba([255, 0, 0, 255])),
+            (53.24, 80.09, 67.20),
+            0.05
+        ));
+    }
+
+    #[test]
+    fn key_order_test() {
+        let mut keys = [Key(2.5), Key(f64::NAN), Key(-1.0), Key(2.25), Key(0.0)];
+        keys.sort();
+        assert_eq!(keys[..4], [Key(-1.0), Key(0.0), Key(2.25), Key(2.5)]);
+        assert!(keys[4].0.is_nan());
+        assert_eq!(Key::from(u32::MAX).0, u32::MAX as f64);
+    }
+
+    #[test]
+    fn hue_precision_test() {
+        // Neighbouring hues no longer collapse onto the same key.
+        assert!(hue(Rgba([255, 0, 1, 255])) > hue(Rgba([255, 1, 0, 255])));
+        assert!(hue(Rgba([255, 2, 0, 255])) > hue(Rgba([255, 1, 0, 255])));
+    }
+
+    #[test]
+    fn perceptual_range_test() {
+        for c in [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 0, 255],
+            [0, 255, 255],
+        ] {
+            let c = Rgba([c[0], c[1], c[2], 255]);
+            for f in [ok_lightness, ok_chroma, ok_hue, lab_l, lab_a, lab_b] {
+                assert!((0.0..=255.0).contains(&f(c).0));
+            }
+        }
+    }
+
+    #[test]
+    fn distance_test() {
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let orange = Rgba([255, 128, 0, 255]);
+        let near = Rgba([250, 130, 10, 255]);
+        for space in [ColorSpace::OkLab, ColorSpace::Lab] {
+            let d = distance([255, 128, 0], space);
+            assert_eq!(d.key(orange), Key(0.0));
+            assert!(d.key(near) < d.key(black));
+            assert!(d.key(near) < d.key(white));
+        }
+        let d = distance([0, 0, 0], ColorSpace::OkLab);
+        assert!((d.key(white).0 - 255.0).abs() < 0.5);
+        let d = distance([0, 0, 0], ColorSpace::Lab);
+        assert!((d.key(white).0 - 100.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn composite_test() {
+        let dark_red = Rgba([100, 0, 0, 255]);
+        let dark_blue = Rgba([0, 0, 100, 255]);
+        let light_red = Rgba([250, 0, 0, 255]);
+        let f = composite_fn(&Composite::TieBreak(vec![SortKey::Green, SortKey::Blue]));
+        assert!(f.key(dark_red) < f.key(dark_blue));
+        assert!(f.key(light_red) < f.key(dark_blue));
+        let f = composite_fn(&Composite::TieBreak(vec![SortKey::Blue, SortKey::Red]));
+        assert!(f.key(dark_red) < f.key(light_red));
+        assert!(f.key(light_red) < f.key(dark_blue));
+        let f = composite_fn(&Composite::Blend(vec![
+            (SortKey::Red, 0.7),
+            (SortKey::Blue, 0.3),
+        ]));
+        assert_eq!(
+            f.key(Rgba([100, 0, 200, 255])),
+            Key(0.7f32 as f64 * 100.0 + 0.3f32 as f64 * 200.0)
+        );
+    }
+
+    #[test]
+    fn hue_origin_test() {
+        let red = Rgba([255, 0, 0, 255]);
+        let green = Rgba([0, 255, 0, 255]);
+        let f = key_fn(SortKey::HueFrom(HueOrigin::Degrees(120.0)));
+        assert_eq!(f.key(green), Key(0.0));
+        assert!((f.key(red).0 - 170.0).abs() < 1e-3);
+        // Reds on both sides of 0 degrees sort together before the green.
+        let f = key_fn(SortKey::HueFrom(HueOrigin::Auto));
+        let colors = [Rgba([255, 0, 20, 255]), green, Rgba([255, 20, 0, 255]), red];
+        let mut keys = colors.iter().map(|c| f.key(*c)).collect::<Vec<_>>();
+        f.adjust_line(&mut keys);
+        assert!(keys[0] < keys[3] && keys[3] < keys[2] && keys[2] < keys[1]);
+        // Composites adjust the line of each of their keys.
+        for composite in [
+            Composite::TieBreak(vec![SortKey::HueFrom(HueOrigin::Auto), SortKey::Lightness]),
+            Composite::Blend(vec![(SortKey::HueFrom(HueOrigin::Auto), 1.0)]),
+        ] {
+            let f = composite_fn(&composite);
+            let keys = f.line_keys(&colors);
+            assert!(keys[0] < keys[3] && keys[3] < keys[2] && keys[2] < keys[1]);
+        }
+    }
+
+    #[test]
+    fn key_buffer_test() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 3, |x, y| {
+            Rgba([(x * 60) as u8, (y * 100) as u8, 7, 255])
+        }));
+        let keys = KeyBuffer::new(&img, &red);
+        assert_eq!((keys.width(), keys.height()), (4, 3));
+        assert_eq!(keys.line(&[(3, 2)]), vec![Key(180.0)]);
+        assert_eq!(keys.line(&[(1, 0), (0, 2)]), vec![Key(60.0), Key(0.0)]);
+        // Keys found by line see the pixels of the line.
+        let f = composite_fn(&Composite::TieBreak(vec![
+            SortKey::HueFrom(HueOrigin::Auto),
+            SortKey::Red,
+        ]));
+        let keys = KeyBuffer::new(&img, f.as_ref());
+        let line = [(1, 0), (2, 1), (0, 2)];
+        let pixels = line.map(|(x, y)| img.get_pixel(x, y));
+        assert_eq!(
+            keys.line(&line.map(|(x, y)| (x as usize, y as usize))),
+            f.line_keys(&pixels)
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! Split a row or column into the spans that get sorted.
+
+use crate::core::*;
+use crate::matrix::*;
+use crate::sortfns::{gradient, Key};
+use image::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use std::ops::Range;
+
+/// Splits the rows and columns of an image into spans. Anything that depends
+/// on the whole image, like the edges, is computed once when it is created.
+/// Only pixels where the optional mask is at least half white are sorted.
+pub struct Segmenter {
+    mode: SpanMode,
+    edges: Option<Matrix<bool>>,
+    mask: Option<Matrix<bool>>,
+    origin: (usize, usize),
+}
+
+impl Segmenter {
+    pub fn new(img: &DynamicImage, mode: SpanMode, mask: Option<&GrayImage>) -> Self {
+        Self::region(img, mode, mask, (0, 0, img.width(), img.height()))
+    }
+
+    /// A segmenter for the rectangle `(x, y, width, height)` of an image that
+    /// only holds the edges and mask of the rectangle, to split a large image
+    /// into strips. The mask covers just the rectangle. Spans still take
+    /// coordinates in the whole image and match those of `new` inside it.
+    pub fn region(
+        img: &DynamicImage,
+        mode: SpanMode,
+        mask: Option<&GrayImage>,
+        (x, y, width, height): (u32, u32, u32, u32),
+    ) -> Self {
+        let edges = match mode {
+            SpanMode::Edge { threshold } if (width, height) == img.dimensions() => {
+                Some(edges(img, threshold))
+            }
+            SpanMode::Edge { threshold } => {
+                // The gradient needs the pixels one beyond the rectangle.
+                let (x0, y0) = (x.saturating_sub(1), y.saturating_sub(1));
+                let x1 = (x + width + 1).min(img.width());
+                let y1 = (y + height + 1).min(img.height());
+                let halo = edges(&img.crop_imm(x0, y0, x1 - x0, y1 - y0), threshold);
+                let (dx, dy) = ((x - x0) as usize, (y - y0) as usize);
+                Some(Matrix::generate(width as usize, height as usize, |i, j| {
+                    halo[j + dy][i + dx]
+                }))
+            }
+            _ => None,
+        };
+        let mask = mask.map(|m| {
+            assert_eq!(m.dimensions(), (width, height));
+            Matrix::generate(m.width(), m.height(), |x, y| {
+                m.get_pixel(x as u32, y as u32)[0] >= 128
+            })
+        });
+        Self {
+            mode,
+            edges,
+            mask,
+            origin: (x as usize, y as usize),
+        }
+    }
+
+    /// The spans of a row or column given the sort key and the location in
+    /// the image of each of its pixels. `line` is the index of the row or
+    /// column, random spans depend on it so that every line gets its own.
+    pub fn spans(&self, keys: &[Key], coords: &[(usize, usize)], line: usize) -> Vec<Range<usize>> {
+        let spans = self.mode_spans(keys, coords, line);
+        match &self.mask {
+            None => spans,
+            Some(mask) => spans
+                .into_iter()
+                .flat_map(|span| {
+                    runs(span.len(), |i| {
+                        let (x, y) = coords[span.start + i];
+                        mask[y - self.origin.1][x - self.origin.0]
+                    })
+                    .into_iter()
+                    .map(move |r| span.start + r.start..span.start + r.end)
+                })
+                .collect(),
+        }
+    }
+
+    fn mode_spans(
+        &self,
+        keys: &[Key],
+        coords: &[(usize, usize)],
+        line: usize,
+    ) -> Vec<Range<usize>> {
+        match self.mode {
+            SpanMode::Full => std::iter::once(0..keys.len()).collect(),
+            SpanMode::Threshold { lower, upper } => {
+                let (lower, upper) = (Key::from(lower), Key::from(upper));
+                runs(keys.len(), |i| keys[i] >= lower && keys[i] <= upper)
+            }
+            SpanMode::Edge { .. } => {
+                let edges = self.edges.as_ref().unwrap();
+                let (x0, y0) = self.origin;
+                runs(keys.len(), |i| !edges[coords[i].1 - y0][coords[i].0 - x0])
+            }
+            SpanMode::Random {
+                min,
+                max,
+                skip,
+                seed,
+            } => random_spans(keys.len(), min, max, skip, seed, line),
+        }
+    }
+}
+
+// Chop a line into random length spans. Each line draws from its own stream
+// of the seeded generator so the spans only depend on the seed and the line.
+// A skip probability that is not a number counts as 0.
+fn random_spans(
+    len: usize,
+    min: usize,
+    max: usize,
+    skip: f32,
+    seed: u64,
+    line: usize,
+) -> Vec<Range<usize>> {
+    let min = min.max(1);
+    let max = max.max(min);
+    let skip = if skip.is_nan() {
+        0.0
+    } else {
+        skip.clamp(0.0, 1.0) as f64
+    };
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(line as u64);
+    let mut result = Vec::new();
+    let mut start = 0;
+    while start < len {
+        let end = (start + rng.gen_range(min..=max)).min(len);
+        if !rng.gen_bool(skip) {
+            result.push(start..end);
+        }
+        start = end;
+    }
+    result
+}
+
+// Mark the pixels whose Sobel gradient magnitude of the luma is at least
+// threshold.
+fn edges(img: &DynamicImage, threshold: u16) -> Matrix<bool> {
+    let grad = gradient(img);
+    let t = threshold as i32;
+    let data = grad
+        .data
+        .par_iter()
+        .map(|(gx, gy)| gx * gx + gy * gy >= t * t)
+        .collect();
+    Matrix::new(grad.width, grad.height, data)
+}
+
+// The maximal runs of consecutive indices for which `inside` is true.
+fn runs<F: Fn(usize) -> bool>(len: usize, inside: F) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    let mut start = None;
+    for i in 0..len {
+        match (inside(i), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                result.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        result.push(s..len);
+    }
+    result
+}
+
+/// The new order of the positions of a row or column after sorting each span
+/// by key. Position `i` of the result holds the index of the pixel that moves
+/// there, positions outside the spans map to themselves.
+/// Spans whose keys are whole numbers in a small range, like all the 8 bit
+/// keys, are counting sorted, the rest with a stable comparison sort. Both
+/// give the same order.
+pub fn sort_order(keys: &[Key], spans: &[Range<usize>], order: SortOrder) -> Vec<usize> {
+    let mut result = (0..keys.len()).collect::<Vec<_>>();
+    for span in spans {
+        match small_range(&keys[span.clone()]) {
+            Some((min, buckets)) => {
+                counting_sort(&mut result[span.clone()], keys, min, buckets, order)
+            }
+            None => comparison_sort(&mut result[span.clone()], keys, order),
+        }
+    }
+    result
+}
+
+// The most buckets a counting sort may use.
+const MAX_BUCKETS: usize = 1024;
+
+// The smallest key and the number of buckets needed if every key is a whole
+// number and they fit in at most MAX_BUCKETS buckets. Negative zero orders
+// before zero so it is left to the comparison sort.
+fn small_range(keys: &[Key]) -> Option<(i64, usize)> {
+    let (mut min, mut max) = (i64::MAX, i64::MIN);
+    for Key(k) in keys {
+        if k.fract() != 0.0 || k.abs() > 1e15 || (*k == 0.0 && k.is_sign_negative()) {
+            return None;
+        }
+        min = min.min(*k as i64);
+        max = max.max(*k as i64);
+    }
+    let buckets = max.checked_sub(min)? as usize + 1;
+    (buckets <= MAX_BUCKETS).then_some((min, buckets))
+}
+
+// Stable counting sort of the pixel indices in `span` by key.
+fn counting_sort(span: &mut [usize], keys: &[Key], min: i64, buckets: usize, order: SortOrder) {
+    let bucket = |i: usize| {
+        let b = (keys[i].0 as i64 - min) as usize;
+        match order {
+            SortOrder::Ascending => b,
+            SortOrder::Descending => buckets - 1 - b,
+        }
+    };
+    let mut starts = vec![0; buckets + 1];
+    for &i in span.iter() {
+        starts[bucket(i) + 1] += 1;
+    }
+    for b in 1..=buckets {
+        starts[b] += starts[b - 1];
+    }
+    let unsorted = span.to_vec();
+    for i in unsorted {
+        let b = bucket(i);
+        span[starts[b]] = i;
+        starts[b] += 1;
+    }
+}
+
+fn comparison_sort(span: &mut [usize], keys: &[Key], order: SortOrder) {
+    span.par_sort_by(|a, b| match order {
+        SortOrder::Ascending => keys[*a].cmp(&keys[*b]),
+        SortOrder::Descending => keys[*b].cmp(&keys[*a]),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_coords(width: usize) -> Vec<(usize, usize)> {
+        (0..width).map(|x| (x, 0)).collect()
+    }
+
+    fn keys(ks: &[u8]) -> Vec<Key> {
+        ks.iter().map(|&k| k.into()).collect()
+    }
+
+    #[test]
+    fn full_test() {
+        let img = DynamicImage::new_rgba8(3, 1);
+        let seg = Segmenter::new(&img, SpanMode::Full, None);
+        assert_eq!(seg.spans(&keys(&[3, 1, 2]), &row_coords(3), 0), vec![0..3]);
+    }
+
+    #[test]
+    fn threshold_test() {
+        let img = DynamicImage::new_rgba8(9, 1);
+        let mode = SpanMode::Threshold {
+            lower: 10,
+            upper: 20,
+        };
+        let seg = Segmenter::new(&img, mode, None);
+        let keys = keys(&[15, 12, 30, 5, 20, 10, 11, 40, 19]);
+        assert_eq!(seg.spans(&keys, &row_coords(9), 0), vec![0..2, 4..7, 8..9]);
+    }
+
+    #[test]
+    fn edge_test() {
+        // A black row with a white block in the middle has edges on both
+        // sides of the block.
+        let img = RgbaImage::from_fn(8, 3, |x, _| {
+            if (3..5).contains(&x) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let seg = Segmenter::new(
+            &DynamicImage::ImageRgba8(img),
+            SpanMode::Edge { threshold: 200 },
+            None,
+        );
+        let coords = (0..8).map(|x| (x, 1)).collect::<Vec<_>>();
+        assert_eq!(seg.spans(&keys(&[0; 8]), &coords, 1), vec![0..2, 6..8]);
+    }
+
+    #[test]
+    fn region_test() {
+        // A region sees the same edges and mask as the whole image.
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(12, 9, |x, y| {
+            let v = ((x * 37 + y * 101) % 251) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        let mask = GrayImage::from_fn(12, 9, |x, y| Luma([if (x + y) % 5 < 3 { 255 } else { 0 }]));
+        let mode = SpanMode::Edge { threshold: 150 };
+        let whole = Segmenter::new(&img, mode, Some(&mask));
+        let rect = (3, 2, 6, 4);
+        let part = imageops::crop_imm(&mask, rect.0, rect.1, rect.2, rect.3).to_image();
+        let region = Segmenter::region(&img, mode, Some(&part), rect);
+        for y in 2..6 {
+            let coords = (3..9).map(|x| (x, y)).collect::<Vec<_>>();
+            assert_eq!(
+                region.spans(&keys(&[0; 6]), &coords, y),
+                whole.spans(&keys(&[0; 6]), &coords, y)
+            );
+        }
+    }
+
+    #[test]
+    fn random_test() {
+        let img = DynamicImage::new_rgba8(100, 1);
+        let mode = SpanMode::Random {
+            min: 5,
+            max: 20,
+            skip: 0.0,
+            seed: 42,
+        };
+        let seg = Segmenter::new(&img, mode, None);
+        let keys = keys(&[0; 100]);
+        let spans = seg.spans(&keys, &row_coords(100), 3);
+        assert_eq!(spans, seg.spans(&keys, &row_coords(100), 3));
+        assert_ne!(spans, seg.spans(&keys, &row_coords(100), 4));
+        assert_eq!(spans.first().unwrap().start, 0);
+        assert_eq!(spans.last().unwrap().end, 100);
+        for (a, b) in spans.iter().zip(spans.iter().skip(1)) {
+            assert_eq!(a.end, b.start);
+        }
+        for span in &spans[..spans.len() - 1] {
+            assert!((5..=20).contains(&span.len()));
+        }
+        // A skip that is not a number skips nothing.
+        assert_eq!(random_spans(100, 5, 20, f32::NAN, 42, 3), spans);
+    }
+
+    #[test]
+    fn mask_test() {
+        let img = DynamicImage::new_rgba8(6, 1);
+        let mask = GrayImage::from_fn(6, 1, |x, _| Luma([if x == 2 { 0 } else { 255 }]));
+        let mode = SpanMode::Threshold {
+            lower: 0,
+            upper: 10,
+        };
+        let seg = Segmenter::new(&img, mode, Some(&mask));
+        let keys = keys(&[0, 0, 0, 0, 20, 0]);
+        assert_eq!(seg.spans(&keys, &row_coords(6), 0), vec![0..2, 3..4, 5..6]);
+    }
+
+    #[test]
+    fn sort_order_test() {
+        let keys = keys(&[15, 12, 30, 5, 20, 10, 11]);
+        let order = sort_order(&keys, &[0..2, 4..7], SortOrder::Ascending);
+        assert_eq!(order, vec![1, 0, 2, 3, 5, 6, 4]);
+        let order = sort_order(&keys, &[0..2, 4..7], SortOrder::Descending);
+        assert_eq!(order, vec![0, 1, 2, 3, 4, 6, 5]);
+        // Equal keys keep their order in both directions.
+        let keys = [Key(0.5), Key(1.0), Key(0.5), Key(1.0), Key(0.0)];
+        let order = sort_order(&keys, &[0..4, 4..5], SortOrder::Descending);
+        assert_eq!(order, vec![1, 3, 0, 2, 4]);
+        let order = sort_order(&keys, &[0..4, 4..5], SortOrder::Ascending);
+        assert_eq!(order, vec![0, 2, 1, 3, 4]);
+    }
+
+    #[test]
+    fn counting_sort_test() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        for max in [2, 16, 256, 1024] {
+            let keys = (0..500)
+                .map(|_| Key(rng.gen_range(0..max) as f64 - 8.0))
+                .collect::<Vec<_>>();
+            assert!(small_range(&keys).is_some());
+            for order in [SortOrder::Ascending, SortOrder::Descending] {
+                let mut expected = (0..500).collect::<Vec<_>>();
+                comparison_sort(&mut expected[0..50], &keys, order);
+                comparison_sort(&mut expected[100..450], &keys, order);
+                assert_eq!(sort_order(&keys, &[0..50, 100..450], order), expected);
+            }
+        }
+        assert_eq!(small_range(&[Key(0.0), Key(2000.0)]), None);
+        assert_eq!(small_range(&[Key(1.0), Key(2.5)]), None);
+        assert_eq!(small_range(&[Key(-0.0), Key(2.0)]), None);
+    }
+}